@@ -1,29 +1,77 @@
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::env;
 use std::fs::File;
 use std::io::{BufRead, BufReader};
 
+struct Elf {
+	/// 1-based position in the input
+	index: usize,
+	calories: u32,
+	/// Elves with the same calories share the same rank, skipping the ranks after them
+	rank: usize,
+}
+
+/// The k elves with the most calories, where ties at the cutoff go to the earlier elf
+fn top_k(calories: &[u32], k: usize) -> Vec<Elf> {
+	// Min-heap of the best so far, where the worst is the lowest total and then the latest elf
+	let mut heap = BinaryHeap::with_capacity(k.min(calories.len()) + 1);
+	for (i, total) in calories.iter().enumerate() {
+		heap.push(Reverse((*total, Reverse(i + 1))));
+		if heap.len() > k {
+			heap.pop();
+		}
+	}
+
+	let mut elves: Vec<Elf> = heap.into_sorted_vec()
+		.into_iter()
+		.map(|Reverse((calories, Reverse(index)))| Elf { index, calories, rank: 0 })
+		.collect();
+
+	for i in 0..elves.len() {
+		elves[i].rank = if i > 0 && elves[i - 1].calories == elves[i].calories {
+			elves[i - 1].rank
+		} else {
+			i + 1
+		};
+	}
+	elves
+}
+
 fn main() {
+	// Usage: day01 [k]
+	let k = env::args().nth(1)
+		.map_or(3, |arg| arg.parse().unwrap());
+
 	let file = File::open("./day01/input").unwrap();
 	let lines = BufReader::new(file).lines();
 
-	let mut current = 0_u32;
+	let mut current = None;
 	let mut results = Vec::new();
 
 	for result in lines {
 		let line = result.unwrap();
 
 		if let Ok(value) = line.parse::<u32>() {
-			current += value;
-		} else {
-			results.push(current);
-			current = 0_u32;
+			current = Some(current.unwrap_or(0_u32) + value);
+		} else if let Some(total) = current.take() {
+			results.push(total);
 		}
 	}
+	results.extend(current);
 
-	results.sort_by(|a, b| b.cmp(a));
+	let top = top_k(&results, k.max(1));
 
 	// Part 1
-	println!("Most calories: {}", results.first().unwrap());
+	match top.first() {
+		Some(elf) => println!("Most calories: {} (elf {})", elf.calories, elf.index),
+		None => println!("No elves"),
+	}
 
 	// Part 2
-	println!("Top 3 most calories: {}", results[0..3].iter().sum::<u32>());
+	let top = &top[..top.len().min(k)];
+	for elf in top {
+		println!("{}. Elf {}: {}", elf.rank, elf.index, elf.calories);
+	}
+	println!("Top {} most calories: {}", top.len(), top.iter().map(|elf| elf.calories).sum::<u32>());
 }
@@ -1,65 +1,490 @@
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::env;
 use std::fs::File;
-use std::io::{BufRead, BufReader};
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::process;
 use std::str::FromStr;
 
-#[derive(Eq, PartialEq, Hash)]
+/// Coordinates are read as i32, but kept as i64 so the air around the cubes always fits
+#[derive(Eq, PartialEq, Hash, Copy, Clone)]
 struct Position {
-	x: u32,
-	y: u32,
-	z: u32,
+	x: i64,
+	y: i64,
+	z: i64,
 }
 
 impl Position {
-	fn new(x: u32, y: u32, z: u32) -> Self {
+	fn new(x: i64, y: i64, z: i64) -> Self {
 		Self { x, y, z }
 	}
+
+	fn from_axes(axes: [i64; 3]) -> Self {
+		Self::new(axes[0], axes[1], axes[2])
+	}
+
+	fn axes(&self) -> [i64; 3] {
+		[self.x, self.y, self.z]
+	}
+
+	/// Neighbours ordered as -x, +x, -y, +y, -z, +z
+	fn neighbours(&self) -> [Position; 6] {
+		[
+			Position::new(self.x - 1, self.y, self.z),
+			Position::new(self.x + 1, self.y, self.z),
+			Position::new(self.x, self.y - 1, self.z),
+			Position::new(self.x, self.y + 1, self.z),
+			Position::new(self.x, self.y, self.z - 1),
+			Position::new(self.x, self.y, self.z + 1),
+		]
+	}
 }
 
 impl FromStr for Position {
 	type Err = ();
 
 	fn from_str(s: &str) -> Result<Self, Self::Err> {
-		let parts: Vec<u32> = s.split(',')
-			.map(|part| part.parse::<u32>().unwrap())
+		let parts: Vec<i64> = s.split(',')
+			.map(|part| part.trim().parse::<i32>().unwrap() as i64)
 			.collect();
 		Ok(Position::new(parts[0], parts[1], parts[2]))
 	}
 }
 
-fn get_open_sides(positions: &HashSet<Position>) -> u32 {
-	let mut result = 0;
+/// Dense 3D grid with one bit per cell, covering the bounding box of all cubes
+struct Grid {
+	min: Position,
+	max: Position,
+	width: usize,
+	height: usize,
+	cells: Vec<u64>,
+}
+
+/// Most cells a dense grid may cover, 512 MiB of bits, so cubes have to be within around
+/// 1600 of each other on every axis
+const MAX_CELLS: usize = 1 << 32;
 
-	for pos in positions {
-		if pos.x == 0 || !positions.contains(&Position::new(pos.x - 1, pos.y, pos.z)) {
-			result += 1;
+/// Number of cells between the corners, if small enough for a dense grid
+fn cell_count(min: &Position, max: &Position) -> Option<usize> {
+	let width = (max.x - min.x + 1) as usize;
+	let height = (max.y - min.y + 1) as usize;
+	let depth = (max.z - min.z + 1) as usize;
+
+	width.checked_mul(height)
+		.and_then(|cells| cells.checked_mul(depth))
+		.filter(|cells| *cells <= MAX_CELLS)
+}
+
+impl Grid {
+	fn empty(min: Position, max: Position) -> Self {
+		let cells = cell_count(&min, &max).expect("Bounding box is too large for a dense grid");
+
+		Self {
+			min,
+			max,
+			width: (max.x - min.x + 1) as usize,
+			height: (max.y - min.y + 1) as usize,
+			cells: vec![0; cells.div_ceil(64)],
 		}
-		if !positions.contains(&Position::new(pos.x + 1, pos.y, pos.z)) {
-			result += 1;
+	}
+
+	/// Grid containing the positions, or None if it, with a layer of air around it,
+	/// is too large to store densely
+	fn new(positions: &[Position]) -> Option<Self> {
+		let mut min = Position::new(i64::MAX, i64::MAX, i64::MAX);
+		let mut max = Position::new(i64::MIN, i64::MIN, i64::MIN);
+
+		for pos in positions {
+			min = Position::new(min.x.min(pos.x), min.y.min(pos.y), min.z.min(pos.z));
+			max = Position::new(max.x.max(pos.x), max.y.max(pos.y), max.z.max(pos.z));
 		}
-		if pos.y == 0 || !positions.contains(&Position::new(pos.x, pos.y - 1, pos.z)) {
-			result += 1;
+
+		if positions.is_empty() {
+			min = Position::new(0, 0, 0);
+			max = Position::new(-1, -1, -1);
 		}
-		if !positions.contains(&Position::new(pos.x, pos.y + 1, pos.z)) {
-			result += 1;
+
+		let outer_min = Position::new(min.x - 1, min.y - 1, min.z - 1);
+		let outer_max = Position::new(max.x + 1, max.y + 1, max.z + 1);
+		cell_count(&outer_min, &outer_max)?;
+
+		let mut grid = Self::empty(min, max);
+
+		for pos in positions {
+			grid.insert(pos);
 		}
-		if pos.z == 0 || !positions.contains(&Position::new(pos.x, pos.y, pos.z - 1)) {
-			result += 1;
+
+		Some(grid)
+	}
+
+	fn index(&self, pos: &Position) -> Option<usize> {
+		if pos.x < self.min.x || pos.x > self.max.x
+			|| pos.y < self.min.y || pos.y > self.max.y
+			|| pos.z < self.min.z || pos.z > self.max.z
+		{
+			return None;
 		}
-		if !positions.contains(&Position::new(pos.x, pos.y, pos.z + 1)) {
-			result += 1;
+
+		let x = (pos.x - self.min.x) as usize;
+		let y = (pos.y - self.min.y) as usize;
+		let z = (pos.z - self.min.z) as usize;
+		Some((z * self.height + y) * self.width + x)
+	}
+
+	fn insert(&mut self, pos: &Position) {
+		let index = self.index(pos).unwrap();
+		self.cells[index / 64] |= 1 << (index % 64);
+	}
+
+	fn contains(&self, pos: &Position) -> bool {
+		match self.index(pos) {
+			Some(index) => self.cells[index / 64] & (1 << (index % 64)) != 0,
+			None => false,
 		}
 	}
 
-	result
+	fn positions(&self) -> impl Iterator<Item=Position> + '_ {
+		self.cells.iter()
+			.enumerate()
+			.filter(|(_, bits)| **bits != 0)
+			.flat_map(|(i, bits)| (0..64)
+				.filter(move |bit| bits & (1 << bit) != 0)
+				.map(move |bit| i * 64 + bit))
+			.map(|index| Position::new(
+				self.min.x + (index % self.width) as i64,
+				self.min.y + (index / self.width % self.height) as i64,
+				self.min.z + (index / self.width / self.height) as i64,
+			))
+	}
+}
+
+/// Side of a cube, facing along an axis (0 = x, 1 = y, 2 = z)
+struct Face {
+	position: Position,
+	axis: usize,
+	positive: bool,
+}
+
+/// Axis aligned rectangle covering one or more faces
+struct Quad {
+	origin: Position,
+	axis: usize,
+	positive: bool,
+	width: i64,
+	height: i64,
+}
+
+/// 6-connected group of cells
+struct Component {
+	volume: u32,
+	surface: u32,
+	min: Position,
+	max: Position,
+}
+
+enum MeshFormat {
+	Obj,
+	Stl,
+}
+
+impl Quad {
+	fn from_face(face: &Face) -> Self {
+		let mut origin = face.position.axes();
+		origin[face.axis] += face.positive as i64;
+
+		Self {
+			origin: Position::from_axes(origin),
+			axis: face.axis,
+			positive: face.positive,
+			width: 1,
+			height: 1,
+		}
+	}
+
+	fn normal(&self) -> [i64; 3] {
+		let mut normal = [0; 3];
+		normal[self.axis] = if self.positive { 1 } else { -1 };
+		normal
+	}
+
+	/// Corners in counter-clockwise order when seen from outside
+	fn corners(&self) -> [[i64; 3]; 4] {
+		let u = (self.axis + 1) % 3;
+		let v = (self.axis + 2) % 3;
+		let origin = self.origin.axes();
+
+		let mut corners = [origin; 4];
+		corners[1][u] += self.width;
+		corners[2][u] += self.width;
+		corners[2][v] += self.height;
+		corners[3][v] += self.height;
+
+		if !self.positive {
+			corners.reverse();
+		}
+		corners
+	}
+}
+
+impl MeshFormat {
+	fn from_path(path: &str) -> Option<Self> {
+		match path.rsplit('.').next()?.to_lowercase().as_str() {
+			"obj" => Some(Self::Obj),
+			"stl" => Some(Self::Stl),
+			_ => None,
+		}
+	}
+
+	fn write(&self, quads: &[Quad], out: &mut impl Write) -> std::io::Result<()> {
+		match self {
+			Self::Obj => write_obj(quads, out),
+			Self::Stl => write_stl(quads, out),
+		}
+	}
+}
+
+/// Flood fills the air reachable from outside the bounding box of the grid
+fn get_outside(grid: &Grid) -> Grid {
+	let min = Position::new(grid.min.x - 1, grid.min.y - 1, grid.min.z - 1);
+	let max = Position::new(grid.max.x + 1, grid.max.y + 1, grid.max.z + 1);
+	let mut outside = Grid::empty(min, max);
+	let mut queue = VecDeque::from([min]);
+	outside.insert(&min);
+
+	while let Some(pos) = queue.pop_front() {
+		for neighbour in pos.neighbours() {
+			if outside.index(&neighbour).is_some()
+				&& !outside.contains(&neighbour)
+				&& !grid.contains(&neighbour)
+			{
+				outside.insert(&neighbour);
+				queue.push_back(neighbour);
+			}
+		}
+	}
+
+	outside
+}
+
+fn get_open_faces(grid: &Grid, exterior_only: bool) -> Vec<Face> {
+	let outside = if exterior_only { Some(get_outside(grid)) } else { None };
+
+	grid.positions()
+		.flat_map(|pos| pos.neighbours().into_iter()
+			.enumerate()
+			.filter(|(_, neighbour)| match &outside {
+				Some(outside) => outside.contains(neighbour),
+				None => !grid.contains(neighbour),
+			})
+			.map(move |(i, _)| Face {
+				position: pos,
+				axis: i / 2,
+				positive: i % 2 == 1,
+			}))
+		.collect()
+}
+
+/// Greedily merges faces in the same plane into as large rectangles as possible
+fn merge_faces(faces: &[Face]) -> Vec<Quad> {
+	let mut planes: HashMap<(usize, bool, i64), HashSet<(i64, i64)>> = HashMap::new();
+
+	for face in faces {
+		let quad = Quad::from_face(face);
+		let origin = quad.origin.axes();
+		planes.entry((quad.axis, quad.positive, origin[quad.axis]))
+			.or_default()
+			.insert((origin[(quad.axis + 1) % 3], origin[(quad.axis + 2) % 3]));
+	}
+
+	let mut quads = Vec::new();
+
+	for ((axis, positive, plane), mut cells) in planes {
+		let mut sorted: Vec<(i64, i64)> = cells.iter().copied().collect();
+		sorted.sort_by_key(|(u, v)| (*v, *u));
+
+		for (u, v) in sorted {
+			if !cells.contains(&(u, v)) {
+				continue;
+			}
+
+			let mut width = 1;
+			while cells.contains(&(u + width, v)) {
+				width += 1;
+			}
+
+			let mut height = 1;
+			while (u..u + width).all(|x| cells.contains(&(x, v + height))) {
+				height += 1;
+			}
+
+			for y in v..v + height {
+				for x in u..u + width {
+					cells.remove(&(x, y));
+				}
+			}
+
+			let mut origin = [0; 3];
+			origin[axis] = plane;
+			origin[(axis + 1) % 3] = u;
+			origin[(axis + 2) % 3] = v;
+
+			quads.push(Quad {
+				origin: Position::from_axes(origin),
+				axis,
+				positive,
+				width,
+				height,
+			});
+		}
+	}
+
+	quads
+}
+
+fn write_obj(quads: &[Quad], out: &mut impl Write) -> std::io::Result<()> {
+	for (i, quad) in quads.iter().enumerate() {
+		for [x, y, z] in quad.corners() {
+			writeln!(out, "v {} {} {}", x, y, z)?;
+		}
+		let first = i * 4 + 1;
+		writeln!(out, "f {} {} {} {}", first, first + 1, first + 2, first + 3)?;
+	}
+	Ok(())
+}
+
+fn write_stl(quads: &[Quad], out: &mut impl Write) -> std::io::Result<()> {
+	writeln!(out, "solid droplet")?;
+	for quad in quads {
+		let [nx, ny, nz] = quad.normal();
+		let corners = quad.corners();
+
+		for triangle in [[0, 1, 2], [0, 2, 3]] {
+			writeln!(out, "facet normal {} {} {}", nx, ny, nz)?;
+			writeln!(out, "outer loop")?;
+			for [x, y, z] in triangle.map(|i| corners[i]) {
+				writeln!(out, "vertex {} {} {}", x, y, z)?;
+			}
+			writeln!(out, "endloop")?;
+			writeln!(out, "endfacet")?;
+		}
+	}
+	writeln!(out, "endsolid droplet")
+}
+
+/// Splits the cells of the grid into 6-connected components
+fn get_components(grid: &Grid) -> Vec<Component> {
+	let mut visited = Grid::empty(grid.min, grid.max);
+	let mut components = Vec::new();
+
+	for start in grid.positions() {
+		if visited.contains(&start) {
+			continue;
+		}
+
+		let mut component = Component {
+			volume: 0,
+			surface: 0,
+			min: start,
+			max: start,
+		};
+		let mut queue = VecDeque::from([start]);
+		visited.insert(&start);
+
+		while let Some(pos) = queue.pop_front() {
+			component.volume += 1;
+			component.min = Position::new(component.min.x.min(pos.x),
+				component.min.y.min(pos.y), component.min.z.min(pos.z));
+			component.max = Position::new(component.max.x.max(pos.x),
+				component.max.y.max(pos.y), component.max.z.max(pos.z));
+
+			for neighbour in pos.neighbours() {
+				if !grid.contains(&neighbour) {
+					component.surface += 1;
+				} else if !visited.contains(&neighbour) {
+					visited.insert(&neighbour);
+					queue.push_back(neighbour);
+				}
+			}
+		}
+
+		components.push(component);
+	}
+
+	components
+}
+
+/// Air inside the bounding box that can't be reached from outside
+fn get_trapped_air(grid: &Grid) -> Grid {
+	let outside = get_outside(grid);
+	let mut air = Grid::empty(grid.min, grid.max);
+
+	for z in grid.min.z..=grid.max.z {
+		for y in grid.min.y..=grid.max.y {
+			for x in grid.min.x..=grid.max.x {
+				let pos = Position::new(x, y, z);
+				if !grid.contains(&pos) && !outside.contains(&pos) {
+					air.insert(&pos);
+				}
+			}
+		}
+	}
+
+	air
+}
+
+fn print_components(name: &str, components: &[Component]) {
+	println!("{} components: {}", name, components.len());
+	for (i, component) in components.iter().enumerate() {
+		println!("{} {}: volume {}, surface {}, bounds {},{},{} to {},{},{}",
+			name, i + 1, component.volume, component.surface,
+			component.min.x, component.min.y, component.min.z,
+			component.max.x, component.max.y, component.max.z);
+	}
+}
+
+fn get_open_sides(grid: &Grid) -> u32 {
+	get_open_faces(grid, false).len() as u32
+}
+
+fn get_exterior_sides(grid: &Grid) -> u32 {
+	get_open_faces(grid, true).len() as u32
 }
 
 fn main() {
 	let file = File::open("./day18/input").unwrap();
-	let positions: HashSet<Position> = BufReader::new(file).lines()
-		.map(|line| line.unwrap().parse::<Position>().unwrap())
+	let positions: Vec<Position> = BufReader::new(file).lines()
+		.map(|line| line.unwrap())
+		.filter(|line| !line.trim().is_empty())
+		.map(|line| line.parse::<Position>().unwrap())
 		.collect();
 
+	let Some(grid) = Grid::new(&positions) else {
+		eprintln!("Bounding box is larger than {} cells, too large for a dense grid", MAX_CELLS);
+		process::exit(1);
+	};
+
 	// Part 1
-	println!("Open sides: {}", get_open_sides(&positions));
+	println!("Open sides: {}", get_open_sides(&grid));
+
+	// Part 2
+	println!("Exterior sides: {}", get_exterior_sides(&grid));
+
+	// Usage: day18 components
+	//    or: day18 <output.obj|output.stl> [exterior] [merge]
+	let args: Vec<String> = env::args().skip(1).collect();
+	if args.first().is_some_and(|arg| arg == "components") {
+		print_components("Lava", &get_components(&grid));
+		print_components("Air", &get_components(&get_trapped_air(&grid)));
+	} else if let Some(path) = args.first() {
+		let format = MeshFormat::from_path(path).expect("Unknown mesh format");
+		let faces = get_open_faces(&grid, args.iter().any(|arg| arg == "exterior"));
+		let quads: Vec<Quad> = if args.iter().any(|arg| arg == "merge") {
+			merge_faces(&faces)
+		} else {
+			faces.iter().map(Quad::from_face).collect()
+		};
+
+		let mut out = BufWriter::new(File::create(path).unwrap());
+		format.write(&quads, &mut out).unwrap();
+		println!("Wrote {} quads to {}", quads.len(), path);
+	}
 }
@@ -1,28 +1,34 @@
 use std::cmp::max;
+use std::env;
 use std::fs::File;
 use std::io::{BufRead, BufReader};
 use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Robot producing one unit of `robot` per minute, costing `costs[resource]` of each resource
+struct Recipe {
+	robot: usize,
+	costs: Vec<u16>,
+}
 
 struct Blueprint {
 	id: u8,
-	ore_robot_ore_cost: u16,
-	clay_robot_ore_cost: u16,
-	obsidian_robot_ore_cost: u16,
-	obsidian_robot_clay_cost: u16,
-	geode_robot_ore_cost: u16,
-	geode_robot_obsidian_cost: u16,
+	resources: Vec<String>,
+	recipes: Vec<Recipe>,
+}
+
+/// Recipes built, as (minute, recipe index), and the final amount of the target resource
+struct Plan {
+	builds: Vec<(u16, usize)>,
+	result: u16,
 }
 
-#[derive(Copy, Clone)]
+#[derive(Clone)]
 struct Resources {
-	ore: u16,
-	clay: u16,
-	obsidian: u16,
-	geode: u16,
-	ore_robots: u16,
-	clay_robots: u16,
-	obsidian_robots: u16,
-	geode_robots: u16,
+	amounts: Vec<u16>,
+	robots: Vec<u16>,
 }
 
 impl FromStr for Blueprint {
@@ -32,148 +38,321 @@ impl FromStr for Blueprint {
 		let id = s["Blueprint ".len()..s.find(':').unwrap()]
 			.parse::<u8>().unwrap();
 
-		let parts: Vec<u16> = s.split(' ')
-			.map(|part| part.parse::<u16>())
-			.filter_map(|part| part.ok())
+		let mut resources: Vec<String> = Vec::new();
+		let mut index_of = |name: &str| -> usize {
+			match resources.iter().position(|resource| resource == name) {
+				Some(index) => index,
+				None => {
+					resources.push(name.to_string());
+					resources.len() - 1
+				}
+			}
+		};
+
+		let mut recipes: Vec<(usize, Vec<(usize, u16)>)> = Vec::new();
+
+		for sentence in s.split("Each ").skip(1) {
+			let (robot, costs) = sentence.split_once(" robot costs ").ok_or(())?;
+			let robot = index_of(robot.trim());
+
+			let costs = costs.trim().trim_end_matches('.')
+				.split(" and ")
+				.map(|cost| {
+					let (amount, name) = cost.trim().split_once(' ').unwrap();
+					(index_of(name.trim()), amount.parse::<u16>().unwrap())
+				})
+				.collect();
+
+			recipes.push((robot, costs));
+		}
+
+		let recipes = recipes.into_iter()
+			.map(|(robot, costs)| {
+				let mut all_costs = vec![0; resources.len()];
+				for (resource, amount) in costs {
+					all_costs[resource] = amount;
+				}
+				Recipe { robot, costs: all_costs }
+			})
 			.collect();
 
 		Ok(Self {
 			id,
-			ore_robot_ore_cost: parts[0],
-			clay_robot_ore_cost: parts[1],
-			obsidian_robot_ore_cost: parts[2],
-			obsidian_robot_clay_cost: parts[3],
-			geode_robot_ore_cost: parts[4],
-			geode_robot_obsidian_cost: parts[5],
+			resources,
+			recipes,
 		})
 	}
 }
 
+impl Blueprint {
+	fn resource(&self, name: &str) -> Option<usize> {
+		self.resources.iter().position(|resource| resource == name)
+	}
+
+	/// Most of each resource that can be spent in one minute
+	fn max_robots(&self) -> Vec<u16> {
+		(0..self.resources.len())
+			.map(|resource| self.recipes.iter()
+				.map(|recipe| recipe.costs[resource])
+				.max()
+				.unwrap_or(0))
+			.collect()
+	}
+}
+
 impl Resources {
-	fn new() -> Self {
+	/// Starts with a single robot of the first recipe
+	fn new(blueprint: &Blueprint) -> Self {
+		let mut robots = vec![0; blueprint.resources.len()];
+		robots[blueprint.recipes[0].robot] = 1;
+
 		Self {
-			ore: 0,
-			clay: 0,
-			obsidian: 0,
-			geode: 0,
-			ore_robots: 1,
-			clay_robots: 0,
-			obsidian_robots: 0,
-			geode_robots: 0,
+			amounts: vec![0; blueprint.resources.len()],
+			robots,
 		}
 	}
 
-	fn mined(&self) -> Self {
+	fn mined(&self, minutes: u16) -> Self {
 		let mut clone = self.clone();
-		clone.ore += clone.ore_robots;
-		clone.clay += clone.clay_robots;
-		clone.obsidian += clone.obsidian_robots;
-		clone.geode += clone.geode_robots;
+		for (amount, robots) in clone.amounts.iter_mut().zip(&self.robots) {
+			*amount += robots * minutes;
+		}
 		clone
 	}
-}
 
-fn build_geode_robot(blueprint: &Blueprint, resources: &Resources) -> Option<Resources> {
-	if resources.ore < blueprint.geode_robot_ore_cost
-		|| resources.obsidian < blueprint.geode_robot_obsidian_cost
-	{
-		return None;
+	/// Minutes to wait until the recipe can be afforded, if ever
+	fn wait_for(&self, recipe: &Recipe) -> Option<u16> {
+		recipe.costs.iter()
+			.enumerate()
+			.map(|(resource, cost)| {
+				let amount = self.amounts[resource];
+				if *cost <= amount {
+					Some(0)
+				} else if self.robots[resource] == 0 {
+					None
+				} else {
+					Some((cost - amount).div_ceil(self.robots[resource]))
+				}
+			})
+			.try_fold(0, |wait, minutes| minutes.map(|minutes| max(wait, minutes)))
 	}
 
-	let mut res = resources.mined();
-	res.ore -= blueprint.geode_robot_ore_cost;
-	res.obsidian -= blueprint.geode_robot_obsidian_cost;
-	res.geode_robots += 1;
-	Some(res)
-}
-
-fn build_obsidian_robot(blueprint: &Blueprint, resources: &Resources) -> Option<Resources> {
-	if resources.ore < blueprint.obsidian_robot_ore_cost
-		|| resources.clay < blueprint.obsidian_robot_clay_cost
-		|| resources.obsidian_robots >= blueprint.geode_robot_obsidian_cost
-	{
-		return None;
+	fn build(&self, recipe: &Recipe, minutes: u16) -> Self {
+		let mut res = self.mined(minutes);
+		for (amount, cost) in res.amounts.iter_mut().zip(&recipe.costs) {
+			*amount -= cost;
+		}
+		res.robots[recipe.robot] += 1;
+		res
 	}
+}
 
-	let mut res = resources.mined();
-	res.ore -= blueprint.obsidian_robot_ore_cost;
-	res.clay -= blueprint.obsidian_robot_clay_cost;
-	res.obsidian_robots += 1;
-	Some(res)
+struct Search<'a> {
+	blueprint: &'a Blueprint,
+	target: usize,
+	minutes: u16,
+	max_robots: Vec<u16>,
+	builds: Vec<(u16, usize)>,
+	best: Plan,
 }
 
-fn build_clay_robot(blueprint: &Blueprint, resources: &Resources) -> Option<Resources> {
-	if resources.ore < blueprint.clay_robot_ore_cost
-		|| resources.clay_robots >= blueprint.obsidian_robot_clay_cost
-	{
-		return None;
+impl Search<'_> {
+	fn step(&mut self, resources: &Resources, remaining: u16) {
+		let current = resources.amounts[self.target] + resources.robots[self.target] * remaining;
+		if current > self.best.result {
+			self.best = Plan {
+				builds: self.builds.clone(),
+				result: current,
+			};
+		}
+
+		// Even building a target robot every minute won't beat the best so far
+		if current + remaining * remaining.saturating_sub(1) / 2 <= self.best.result {
+			return;
+		}
+
+		let blueprint = self.blueprint;
+		for (index, recipe) in blueprint.recipes.iter().enumerate().rev() {
+			if recipe.robot != self.target
+				&& resources.robots[recipe.robot] >= self.max_robots[recipe.robot]
+			{
+				continue;
+			}
+
+			if let Some(wait) = resources.wait_for(recipe) {
+				if wait + 1 < remaining {
+					let res = resources.build(recipe, wait + 1);
+					self.builds.push((self.minutes - remaining + wait + 1, index));
+					self.step(&res, remaining - wait - 1);
+					self.builds.pop();
+				}
+			}
+		}
 	}
+}
 
-	let mut res = resources.mined();
-	res.ore -= blueprint.clay_robot_ore_cost;
-	res.clay_robots += 1;
-	Some(res)
+/// Build order collecting the most of the target resource in time
+fn solve(minutes: u16, blueprint: &Blueprint, target: &str) -> Plan {
+	let mut search = Search {
+		blueprint,
+		target: blueprint.resource(target).expect("Unknown target resource"),
+		minutes,
+		max_robots: blueprint.max_robots(),
+		builds: Vec::new(),
+		best: Plan {
+			builds: Vec::new(),
+			result: 0,
+		},
+	};
+	search.step(&Resources::new(blueprint), minutes);
+	search.best
 }
 
-fn build_ore_robot(blueprint: &Blueprint, resources: &Resources) -> Option<Resources> {
-	if resources.ore < blueprint.ore_robot_ore_cost
-		|| resources.ore_robots >= max(blueprint.clay_robot_ore_cost,
-		max(blueprint.obsidian_robot_ore_cost, blueprint.geode_robot_ore_cost))
-	{
-		return None;
-	}
+/// Solves blueprints on a pool of worker threads, results are in the same order as the blueprints
+fn solve_all(minutes: u16, blueprints: &[Blueprint], target: &str,
+	threads: usize) -> Vec<(Plan, Duration)> {
+	let next = AtomicUsize::new(0);
 
-	let mut res = resources.mined();
-	res.ore -= blueprint.ore_robot_ore_cost;
-	res.ore_robots += 1;
-	Some(res)
+	let mut results: Vec<(usize, Plan, Duration)> = thread::scope(|scope| {
+		let workers: Vec<_> = (0..threads.max(1))
+			.map(|_| scope.spawn(|| {
+				let mut solved = Vec::new();
+				loop {
+					let index = next.fetch_add(1, Ordering::Relaxed);
+					let Some(blueprint) = blueprints.get(index) else {
+						break solved;
+					};
+					let start = Instant::now();
+					let plan = solve(minutes, blueprint, target);
+					solved.push((index, plan, start.elapsed()));
+				}
+			}))
+			.collect();
+
+		workers.into_iter()
+			.flat_map(|worker| worker.join().unwrap())
+			.collect()
+	});
+
+	results.sort_by_key(|(index, _, _)| *index);
+	results.into_iter()
+		.map(|(_, plan, duration)| (plan, duration))
+		.collect()
 }
 
-fn step(blueprint: &Blueprint, resources: Resources, remaining: u16) -> Vec<Resources> {
-	if remaining == 0 {
-		return vec![resources];
+fn quality_level(blueprint: &Blueprint, plan: &Plan) -> u32 {
+	plan.result as u32 * blueprint.id as u32
+}
+
+fn plural(count: u16, word: &str) -> String {
+	if count == 1 {
+		word.to_string()
+	} else {
+		format!("{}s", word)
 	}
-	let next = remaining - 1;
-
-	let res_build = if let Some(res) = build_geode_robot(blueprint, &resources) {
-		Some(res)
-	} else if let Some(res) = build_obsidian_robot(blueprint, &resources) {
-		Some(res)
-	} else if let Some(res) = build_clay_robot(blueprint, &resources) {
-		Some(res)
+}
+
+fn article(word: &str) -> &'static str {
+	if word.starts_with(['a', 'e', 'i', 'o', 'u']) {
+		"an"
 	} else {
-		None
-	};
+		"a"
+	}
+}
 
-	let paths = [
-		build_ore_robot(blueprint, &resources)
-			.map(|res| step(blueprint, res, next)),
-		res_build.map(|res| step(blueprint, res, next)),
-		Some(step(blueprint, resources.mined(), next)),
-	];
+/// Replays the plan minute by minute, like the example in the puzzle
+fn print_plan(minutes: u16, blueprint: &Blueprint, plan: &Plan) {
+	println!("Blueprint {}:", blueprint.id);
+	let mut resources = Resources::new(blueprint);
 
-	paths.into_iter()
-		.filter_map(|path| path)
-		.flatten()
-		.collect()
-}
+	for minute in 1..=minutes {
+		println!();
+		println!("== Minute {} ==", minute);
+
+		let recipe = plan.builds.iter()
+			.find(|(build_minute, _)| *build_minute == minute)
+			.map(|(_, index)| &blueprint.recipes[*index]);
 
-fn simulate(minutes: u16, blueprint: &Blueprint) -> u32 {
-	step(blueprint, Resources::new(), minutes).into_iter()
-		.map(|step| step.geode)
-		.max()
-		.unwrap() as u32
-		* blueprint.id as u32
+		if let Some(recipe) = recipe {
+			let costs: Vec<String> = recipe.costs.iter()
+				.enumerate()
+				.filter(|(_, cost)| **cost > 0)
+				.map(|(resource, cost)| format!("{} {}", cost, blueprint.resources[resource]))
+				.collect();
+			let name = &blueprint.resources[recipe.robot];
+			println!("Spend {} to start building {} {}-collecting robot.",
+				costs.join(" and "), article(name), name);
+		}
+
+		let mined = resources.mined(1);
+		for (resource, robots) in resources.robots.iter().enumerate() {
+			if *robots > 0 {
+				let name = &blueprint.resources[resource];
+				println!("{} {}-collecting {} {} {} {}; you now have {} {}.",
+					robots, name, plural(*robots, "robot"),
+					if *robots == 1 { "collects" } else { "collect" },
+					robots, name, mined.amounts[resource] - recipe.map_or(0, |r| r.costs[resource]),
+					name);
+			}
+		}
+
+		if let Some(recipe) = recipe {
+			resources = resources.build(recipe, 1);
+			let name = &blueprint.resources[recipe.robot];
+			println!("The new {}-collecting robot is ready; you now have {} of them.",
+				name, resources.robots[recipe.robot]);
+		} else {
+			resources = mined;
+		}
+	}
+
+	let totals: Vec<String> = resources.amounts.iter()
+		.zip(&blueprint.resources)
+		.map(|(amount, name)| format!("{} {}", amount, name))
+		.collect();
+	println!();
+	println!("Total: {}", totals.join(", "));
+	println!("Quality level: {}", quality_level(blueprint, plan));
+	println!();
 }
 
 fn main() {
 	let file = File::open("./day19/input").unwrap();
 	let blueprints: Vec<Blueprint> = BufReader::new(file).lines()
-		.map(|line| line.unwrap().parse::<Blueprint>().unwrap())
+		.map(|line| line.unwrap())
+		.filter(|line| !line.trim().is_empty())
+		.map(|line| line.parse::<Blueprint>().unwrap())
 		.collect();
 
+	// Usage: day19 [target resource] [plan] [threads=<count>]
+	let args: Vec<String> = env::args().skip(1).collect();
+	let show_plan = args.iter().any(|arg| arg == "plan");
+	let threads = args.iter()
+		.find_map(|arg| arg.strip_prefix("threads="))
+		.map(|count| count.parse::<usize>().unwrap())
+		.unwrap_or_else(|| thread::available_parallelism().map_or(1, |count| count.get()));
+	let target = args.iter()
+		.find(|arg| *arg != "plan" && !arg.starts_with("threads="))
+		.cloned()
+		.unwrap_or_else(|| String::from("geode"));
+
+	if let Some(blueprint) = blueprints.iter().find(|blueprint| blueprint.resource(&target).is_none()) {
+		println!("Blueprint {} has no resource named {}", blueprint.id, target);
+		return;
+	}
+
+	let results = solve_all(24, &blueprints, &target, threads);
+
+	for (blueprint, (plan, duration)) in blueprints.iter().zip(&results) {
+		if show_plan {
+			print_plan(24, blueprint, plan);
+		}
+		println!("Blueprint {}: {} {} in {:?}", blueprint.id, plan.result, target, duration);
+	}
+
 	// Part 1
 	println!("Quality level: {}", blueprints.iter()
-		.map(|blueprint| simulate(24, blueprint))
+		.zip(&results)
+		.map(|(blueprint, (plan, _))| quality_level(blueprint, plan))
 		.sum::<u32>())
 }
@@ -1,48 +1,154 @@
 use std::fs::File;
 use std::io::{BufRead, BufReader};
 
-#[derive(Copy, Clone)]
-struct Position {
-	index: usize,
-	value: i32,
+/// Circular sequence where elements keep the id of their original index,
+/// split into blocks of around sqrt(n) elements so moves and lookups are O(sqrt(n))
+struct CircularList<T> {
+	values: Vec<T>,
+	blocks: Vec<Vec<usize>>,
+	block_of: Vec<usize>,
+	block_size: usize,
+	changes: usize,
 }
 
-impl Position {
-	fn parse(index: usize, value: &str) -> Self {
-		Self {
-			index,
-			value: value.parse().unwrap(),
+impl<T> CircularList<T> {
+	fn new(values: Vec<T>) -> Self {
+		let block_size = ((values.len() as f64).sqrt() as usize).max(1);
+		let mut list = Self {
+			blocks: vec![(0..values.len()).collect()],
+			block_of: vec![0; values.len()],
+			values,
+			block_size,
+			changes: 0,
+		};
+		list.rebuild();
+		list
+	}
+
+	fn len(&self) -> usize {
+		self.values.len()
+	}
+
+	fn value(&self, id: usize) -> &T {
+		&self.values[id]
+	}
+
+	/// Splits all elements into evenly sized blocks again
+	fn rebuild(&mut self) {
+		let ids: Vec<usize> = self.blocks.drain(..).flatten().collect();
+		self.blocks = ids.chunks(self.block_size)
+			.map(|chunk| chunk.to_vec())
+			.collect();
+
+		for (block, ids) in self.blocks.iter().enumerate() {
+			for id in ids {
+				self.block_of[*id] = block;
+			}
 		}
+		self.changes = 0;
 	}
-}
 
-fn main() {
-	let file = File::open("./day20/input").unwrap();
+	/// Current index of the element, counted from the start of the first block
+	fn index_of(&self, id: usize) -> usize {
+		let block = self.block_of[id];
+		let before: usize = self.blocks[..block].iter().map(|ids| ids.len()).sum();
+		before + self.blocks[block].iter().position(|other| *other == id).unwrap()
+	}
 
-	let positions: Vec<Position> = BufReader::new(file).lines()
-		.enumerate()
-		.map(|(i, line)| Position::parse(i, &line.unwrap()))
-		.collect();
+	/// Id of the element at the index, wrapping around
+	fn id_at(&self, index: usize) -> usize {
+		let mut index = index % self.len();
+		for ids in &self.blocks {
+			if index < ids.len() {
+				return ids[index];
+			}
+			index -= ids.len();
+		}
+		unreachable!()
+	}
+
+	/// Id of the element n steps after the element
+	fn nth_after(&self, id: usize, n: usize) -> usize {
+		self.id_at(self.index_of(id) + n)
+	}
 
-	let mut result = positions.clone();
+	/// Id of the first element matching the predicate
+	fn find(&self, predicate: impl Fn(&T) -> bool) -> Option<usize> {
+		self.values.iter().position(predicate)
+	}
 
-	for position in &positions {
-		let index = result.iter()
-			.position(|pos| pos.index == position.index)
-			.unwrap();
-		let current = result.remove(index);
-		let added = index as i32 + current.value;
-		let result_index = added.rem_euclid(result.len() as i32);
-		result.insert(result_index as usize, current);
+	fn remove(&mut self, id: usize) -> usize {
+		let index = self.index_of(id);
+		let block = &mut self.blocks[self.block_of[id]];
+		let position = block.iter().position(|other| *other == id).unwrap();
+		block.remove(position);
+		index
 	}
 
-	let start_index = result.iter()
-		.position(|pos| pos.value == 0)
-		.unwrap();
+	fn insert(&mut self, id: usize, index: usize) {
+		let mut remaining = index;
+		let mut block = 0;
+		while block < self.blocks.len() - 1 && remaining > self.blocks[block].len() {
+			remaining -= self.blocks[block].len();
+			block += 1;
+		}
 
-	// Part 1
-	println!("Sum: {}", [1_000, 2_000, 3_000]
-		.map(|i| result.iter().cycle().nth(start_index + i).unwrap().value)
+		self.blocks[block].insert(remaining, id);
+		self.block_of[id] = block;
+
+		self.changes += 1;
+		if self.changes > self.block_size {
+			self.rebuild();
+		}
+	}
+
+	/// Moves the element forward, or backward if negative, past other elements
+	fn move_by(&mut self, id: usize, offset: i64) {
+		if self.len() < 2 {
+			return;
+		}
+
+		let index = self.remove(id);
+		let target = (index as i64 + offset).rem_euclid(self.len() as i64 - 1);
+		self.insert(id, target as usize);
+	}
+}
+
+fn mix(list: &mut CircularList<i64>, rounds: usize) {
+	for _ in 0..rounds {
+		for id in 0..list.len() {
+			let offset = *list.value(id);
+			list.move_by(id, offset);
+		}
+	}
+}
+
+fn get_coordinates(list: &CircularList<i64>) -> i64 {
+	let zero = list.find(|value| *value == 0).unwrap();
+	[1_000, 2_000, 3_000]
+		.map(|i| *list.value(list.nth_after(zero, i)))
 		.into_iter()
-		.sum::<i32>());
+		.sum()
+}
+
+fn main() {
+	let file = File::open("./day20/input").unwrap();
+
+	let values: Vec<i64> = BufReader::new(file).lines()
+		.map(|line| line.unwrap())
+		.filter(|line| !line.trim().is_empty())
+		.map(|line| line.trim().parse().unwrap())
+		.collect();
+
+	// Part 1
+	let mut list = CircularList::new(values.clone());
+	mix(&mut list, 1);
+	println!("Sum: {}", get_coordinates(&list));
+
+	// Part 2
+	let mut list = CircularList::new(values.iter()
+		.map(|value| value * 811_589_153)
+		.collect());
+	mix(&mut list, 10);
+	println!("Decrypted sum: {}", get_coordinates(&list));
 }
@@ -1,10 +1,13 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::env;
+use std::fmt::{Display, Formatter};
 use std::fs::File;
-use std::io::{BufRead, BufReader};
+use std::io::{stdout, BufRead, BufReader, Write};
 use std::str::FromStr;
 
 type Monkeys = HashMap<String, Monkey>;
 
+#[derive(Copy, Clone, PartialEq, Eq)]
 enum Operation {
 	Add,
 	Subtract,
@@ -22,7 +25,117 @@ struct Monkey {
 	job: MonkeyJob,
 }
 
+/// Dependencies between monkeys, and problems found in them
+struct Graph<'a> {
+	/// Monkeys needed by root, each after the monkeys it depends on
+	order: Vec<&'a str>,
+	/// Monkeys referring to a monkey that doesn't exist, as (monkey, reference)
+	missing: Vec<(&'a str, &'a str)>,
+	cycles: Vec<Vec<&'a str>>,
+	/// Monkeys not needed by root
+	unused: Vec<&'a str>,
+	needed: HashSet<&'a str>,
+}
+
+/// Values of monkeys, cached as they are needed, where changing a number only invalidates the monkeys depending on it
+struct Evaluator<'a> {
+	monkeys: &'a Monkeys,
+	numbers: HashMap<&'a str, i64>,
+	values: HashMap<&'a str, i64>,
+	parents: HashMap<&'a str, Vec<&'a str>>,
+}
+
+/// Expanded job of a monkey, with everything not depending on the variable folded into numbers
+enum Expression {
+	Number(i64),
+	Variable(String),
+	MathOperation(Box<Expression>, Operation, Box<Expression>),
+}
+
+/// Fraction in lowest terms, with a positive denominator
+#[derive(Copy, Clone, PartialEq, Eq)]
+struct Rational {
+	num: i128,
+	den: i128,
+}
+
+enum MathErrorKind {
+	DivisionByZero,
+	Overflow,
+}
+
+/// Error from evaluating the job of a monkey
+struct MathError {
+	monkey: String,
+	kind: MathErrorKind,
+}
+
+fn gcd(a: i128, b: i128) -> i128 {
+	if b == 0 {
+		a.abs()
+	} else {
+		gcd(b, a % b)
+	}
+}
+
+impl Rational {
+	fn new(num: i128, den: i128) -> Option<Self> {
+		if den == 0 {
+			return None;
+		}
+		let div = gcd(num, den).max(1) * den.signum();
+		Some(Self {
+			num: num.checked_div(div)?,
+			den: den.checked_div(div)?,
+		})
+	}
+
+	fn is_integer(&self) -> bool {
+		self.den == 1
+	}
+
+	/// If the value can be represented the same way as in regular evaluation
+	fn fits_i64(&self) -> bool {
+		self.is_integer() && i64::try_from(self.num).is_ok()
+	}
+}
+
+impl Display for Rational {
+	fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+		if self.is_integer() {
+			write!(f, "{}", self.num)
+		} else {
+			write!(f, "{}/{}", self.num, self.den)
+		}
+	}
+}
+
+impl Display for MathError {
+	fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+		match self.kind {
+			MathErrorKind::DivisionByZero => write!(f, "{}: division by zero", self.monkey),
+			MathErrorKind::Overflow => write!(f, "{}: overflow", self.monkey),
+		}
+	}
+}
+
 impl Operation {
+	fn symbol(&self) -> &'static str {
+		match self {
+			Operation::Add => "+",
+			Operation::Subtract => "-",
+			Operation::Multiply => "*",
+			Operation::Divide => "/",
+		}
+	}
+
+	fn precedence(&self) -> u8 {
+		match self {
+			Operation::Add | Operation::Subtract => 1,
+			Operation::Multiply | Operation::Divide => 2,
+		}
+	}
+
 	fn calc(&self, a: i64, b: i64) -> i64 {
 		match self {
 			Operation::Add => a + b,
@@ -31,6 +144,221 @@ impl Operation {
 			Operation::Divide => a / b,
 		}
 	}
+
+	/// Same as calc, but failing instead of panicking or wrapping around
+	fn calc_checked(&self, a: i64, b: i64) -> Result<i64, MathErrorKind> {
+		if *self == Operation::Divide && b == 0 {
+			return Err(MathErrorKind::DivisionByZero);
+		}
+		match self {
+			Operation::Add => a.checked_add(b),
+			Operation::Subtract => a.checked_sub(b),
+			Operation::Multiply => a.checked_mul(b),
+			Operation::Divide => a.checked_div(b),
+		}.ok_or(MathErrorKind::Overflow)
+	}
+
+	fn calc_exact(&self, a: Rational, b: Rational) -> Result<Rational, MathErrorKind> {
+		let result = match self {
+			Operation::Add => a.num.checked_mul(b.den)
+				.zip(b.num.checked_mul(a.den))
+				.and_then(|(x, y)| x.checked_add(y))
+				.zip(a.den.checked_mul(b.den)),
+			Operation::Subtract => a.num.checked_mul(b.den)
+				.zip(b.num.checked_mul(a.den))
+				.and_then(|(x, y)| x.checked_sub(y))
+				.zip(a.den.checked_mul(b.den)),
+			Operation::Multiply => a.num.checked_mul(b.num)
+				.zip(a.den.checked_mul(b.den)),
+			Operation::Divide => {
+				if b.num == 0 {
+					return Err(MathErrorKind::DivisionByZero);
+				}
+				a.num.checked_mul(b.den)
+					.zip(a.den.checked_mul(b.num))
+			}
+		};
+
+		result.and_then(|(num, den)| Rational::new(num, den))
+			.ok_or(MathErrorKind::Overflow)
+	}
+}
+
+impl Expression {
+	fn new(name: &str, variable: &str, monkeys: &Monkeys) -> Self {
+		if name == variable {
+			return Expression::Variable(String::from(name));
+		}
+
+		match &monkeys[name].job {
+			MonkeyJob::MathOperation(name1, op, name2) => Expression::simplify(
+				Expression::new(name1, variable, monkeys),
+				*op,
+				Expression::new(name2, variable, monkeys),
+			),
+			MonkeyJob::Number(num) => Expression::Number(*num),
+		}
+	}
+
+	fn simplify(left: Expression, op: Operation, right: Expression) -> Self {
+		match (left, op, right) {
+			// Division without an integer result, or by zero, is kept as is
+			(Expression::Number(a), op, Expression::Number(b)) => {
+				match op.calc_exact(Rational::new(a as i128, 1).unwrap(), Rational::new(b as i128, 1).unwrap()) {
+					Ok(value) if value.fits_i64() => Expression::Number(value.num as i64),
+					_ => Expression::MathOperation(Box::new(Expression::Number(a)), op,
+						Box::new(Expression::Number(b))),
+				}
+			}
+			(Expression::Number(0), Operation::Add, expr)
+			| (expr, Operation::Add | Operation::Subtract, Expression::Number(0))
+			| (Expression::Number(1), Operation::Multiply, expr)
+			| (expr, Operation::Multiply | Operation::Divide, Expression::Number(1)) => expr,
+			(left, op, right) => Expression::MathOperation(Box::new(left), op, Box::new(right)),
+		}
+	}
+
+	fn precedence(&self) -> u8 {
+		match self {
+			Expression::MathOperation(_, op, _) => op.precedence(),
+			_ => u8::MAX,
+		}
+	}
+}
+
+impl Display for Expression {
+	fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+		match self {
+			Expression::Number(num) => write!(f, "{}", num),
+			Expression::Variable(name) => write!(f, "{}", name),
+			Expression::MathOperation(left, op, right) => {
+				if left.precedence() < op.precedence() {
+					write!(f, "({})", left)?;
+				} else {
+					write!(f, "{}", left)?;
+				}
+
+				write!(f, " {} ", op.symbol())?;
+
+				// Subtraction and division aren't associative
+				if right.precedence() < op.precedence()
+					|| (right.precedence() == op.precedence()
+					&& matches!(op, Operation::Subtract | Operation::Divide))
+				{
+					write!(f, "({})", right)
+				} else {
+					write!(f, "{}", right)
+				}
+			}
+		}
+	}
+}
+
+impl MonkeyJob {
+	fn dependencies(&self) -> Vec<&str> {
+		match self {
+			MonkeyJob::MathOperation(name1, _, name2) => vec![name1, name2],
+			MonkeyJob::Number(_) => Vec::new(),
+		}
+	}
+}
+
+impl<'a> Graph<'a> {
+	fn new(root: &str, monkeys: &'a Monkeys) -> Self {
+		let mut graph = Self {
+			order: Vec::new(),
+			missing: Vec::new(),
+			cycles: Vec::new(),
+			unused: Vec::new(),
+			needed: HashSet::new(),
+		};
+
+		let mut names: Vec<&str> = monkeys.keys().map(|name| name.as_str()).collect();
+		names.sort();
+
+		let mut done = HashSet::new();
+		if let Some((root, _)) = monkeys.get_key_value(root) {
+			graph.order = graph.visit(root, monkeys, &mut done);
+		}
+
+		graph.needed = done.clone();
+		for name in names {
+			if !graph.needed.contains(name) {
+				graph.unused.push(name);
+			}
+			if !done.contains(name) {
+				graph.visit(name, monkeys, &mut done);
+			}
+		}
+
+		graph
+	}
+
+	/// Depth first search from the monkey, returning the visited monkeys in post-order
+	fn visit(&mut self, start: &'a str, monkeys: &'a Monkeys,
+		done: &mut HashSet<&'a str>) -> Vec<&'a str> {
+		let mut order = Vec::new();
+		let mut stack: Vec<(&str, usize)> = vec![(start, 0)];
+
+		while let Some((name, index)) = stack.last_mut() {
+			let name = *name;
+			let dependencies = monkeys[name].job.dependencies();
+
+			if let Some(dependency) = dependencies.get(*index) {
+				*index += 1;
+				match monkeys.get_key_value(*dependency) {
+					None => self.missing.push((name, dependency)),
+					Some(_) if done.contains(dependency) => {}
+					Some((dependency, _)) => {
+						match stack.iter().position(|(other, _)| other == dependency) {
+							Some(start) => self.cycles.push(stack[start..].iter()
+								.map(|(other, _)| *other)
+								.collect()),
+							None => stack.push((dependency, 0)),
+						}
+					}
+				}
+			} else {
+				stack.pop();
+				done.insert(name);
+				order.push(name);
+			}
+		}
+
+		order
+	}
+
+	/// If a missing monkey or cycle stops root from being evaluated
+	fn is_valid(&self) -> bool {
+		self.missing.iter().all(|(name, _)| !self.needed.contains(name))
+			&& self.cycles.iter().all(|cycle| !self.needed.contains(cycle[0]))
+	}
+
+	fn warning(&self, name: &str) -> &'static str {
+		if self.needed.contains(name) {
+			""
+		} else {
+			"Warning: "
+		}
+	}
+
+	/// Expression tree of the monkeys needed by root, in Graphviz DOT format
+	fn write_dot(&self, monkeys: &Monkeys, out: &mut impl Write) -> std::io::Result<()> {
+		writeln!(out, "digraph monkeys {{")?;
+		for name in &self.order {
+			match &monkeys[*name].job {
+				MonkeyJob::MathOperation(name1, op, name2) => {
+					writeln!(out, "\t\"{}\" [label=\"{}\\n{}\"];", name, name, op.symbol())?;
+					writeln!(out, "\t\"{}\" -> \"{}\";", name, name1)?;
+					writeln!(out, "\t\"{}\" -> \"{}\";", name, name2)?;
+				}
+				MonkeyJob::Number(num) => {
+					writeln!(out, "\t\"{}\" [label=\"{}\\n{}\", shape=box];", name, name, num)?;
+				}
+			}
+		}
+		writeln!(out, "}}")
+	}
 }
 
 impl FromStr for Operation {
@@ -80,15 +408,138 @@ impl FromStr for Monkey {
 	}
 }
 
-fn get_value(name: &str, monkeys: &Monkeys) -> i64 {
-	match &monkeys[name].job {
-		MonkeyJob::MathOperation(name1, op, name2) => {
-			let val1 = get_value(name1, monkeys);
-			let val2 = get_value(name2, monkeys);
-			op.calc(val1, val2)
+/// Value of every monkey in the graph
+fn get_values<'a>(graph: &Graph<'a>, monkeys: &Monkeys) -> HashMap<&'a str, i64> {
+	let mut values: HashMap<&str, i64> = HashMap::new();
+
+	for name in &graph.order {
+		let value = match &monkeys[*name].job {
+			MonkeyJob::MathOperation(name1, op, name2) => {
+				op.calc(values[name1.as_str()], values[name2.as_str()])
+			}
+			MonkeyJob::Number(num) => *num,
+		};
+		values.insert(name, value);
+	}
+
+	values
+}
+
+impl<'a> Evaluator<'a> {
+	fn new(monkeys: &'a Monkeys) -> Self {
+		let mut numbers = HashMap::new();
+		let mut parents: HashMap<&str, Vec<&str>> = HashMap::new();
+
+		for (name, monkey) in monkeys {
+			match &monkey.job {
+				MonkeyJob::MathOperation(name1, _, name2) => {
+					parents.entry(name1).or_default().push(name);
+					parents.entry(name2).or_default().push(name);
+				}
+				MonkeyJob::Number(num) => {
+					numbers.insert(name.as_str(), *num);
+				}
+			}
+		}
+
+		Self {
+			monkeys,
+			numbers,
+			values: HashMap::new(),
+			parents,
+		}
+	}
+
+	/// Changes the number yelled by a monkey, returns false if it doesn't yell a number
+	fn set(&mut self, name: &str, value: i64) -> bool {
+		let Some(number) = self.numbers.get_mut(name) else {
+			return false;
+		};
+		*number = value;
+
+		// Monkeys without a cached value never have cached parents
+		let mut queue = vec![name];
+		while let Some(current) = queue.pop() {
+			if self.values.remove(current).is_some() {
+				if let Some(parents) = self.parents.get(current) {
+					queue.extend(parents);
+				}
+			}
 		}
-		MonkeyJob::Number(num) => *num,
+		true
+	}
+
+	fn get(&mut self, name: &str) -> Result<i64, MathError> {
+		let (name, _) = self.monkeys.get_key_value(name).unwrap();
+		let mut stack: Vec<&str> = vec![name];
+
+		while let Some(current) = stack.last().copied() {
+			if self.values.contains_key(current) {
+				stack.pop();
+				continue;
+			}
+
+			match &self.monkeys[current].job {
+				MonkeyJob::MathOperation(name1, op, name2) => {
+					match (self.values.get(name1.as_str()), self.values.get(name2.as_str())) {
+						(Some(val1), Some(val2)) => {
+							let value = op.calc_checked(*val1, *val2)
+								.map_err(|kind| MathError {
+									monkey: String::from(current),
+									kind,
+								})?;
+							self.values.insert(current, value);
+							stack.pop();
+						}
+						(val1, val2) => {
+							if val1.is_none() {
+								stack.push(name1);
+							}
+							if val2.is_none() {
+								stack.push(name2);
+							}
+						}
+					}
+				}
+				MonkeyJob::Number(_) => {
+					self.values.insert(current, self.numbers[current]);
+					stack.pop();
+				}
+			}
+		}
+
+		Ok(self.values[name.as_str()])
+	}
+}
+
+/// Evaluates using exact fractions, collecting monkeys yelling values not fitting an i64
+fn get_exact_value(graph: &Graph, monkeys: &Monkeys,
+	flagged: &mut Vec<(String, Rational)>) -> Result<Rational, MathError> {
+	let mut values: HashMap<&str, Rational> = HashMap::new();
+
+	for name in &graph.order {
+		let value = match &monkeys[*name].job {
+			MonkeyJob::MathOperation(name1, op, name2) => {
+				let val1 = values[name1.as_str()];
+				let val2 = values[name2.as_str()];
+				let value = op.calc_exact(val1, val2)
+					.map_err(|kind| MathError {
+						monkey: String::from(*name),
+						kind,
+					})?;
+
+				// Only flag where the problem starts, not every monkey depending on it
+				if val1.fits_i64() && val2.fits_i64() && !value.fits_i64() {
+					flagged.push((String::from(*name), value));
+				}
+				value
+			}
+			MonkeyJob::Number(num) => Rational::new(*num as i128, 1).unwrap(),
+		};
+		values.insert(name, value);
 	}
+
+	Ok(values[graph.order.last().unwrap()])
 }
 
 fn main() {
@@ -98,6 +549,94 @@ fn main() {
 		.map(|monkey| (String::from(&monkey.name), monkey))
 		.collect();
 
+	let graph = Graph::new("root", &monkeys);
+	for (name, reference) in &graph.missing {
+		println!("{}{} refers to missing monkey {}", graph.warning(name), name, reference);
+	}
+	for cycle in &graph.cycles {
+		println!("{}Cycle: {} -> {}", graph.warning(cycle[0]), cycle.join(" -> "), cycle[0]);
+	}
+	for name in &graph.unused {
+		println!("Unused: {}", name);
+	}
+	if graph.order.is_empty() {
+		println!("No root monkey");
+		return;
+	}
+	if !graph.is_valid() {
+		return;
+	}
+
+	// Usage: day21 [exact|dot|expr [monkey] [variable]|<monkey>=<number>...]
+	let mode = env::args().nth(1);
+	if mode.as_deref() == Some("expr") {
+		let name = env::args().nth(2).unwrap_or_else(|| String::from("root"));
+		let variable = env::args().nth(3).unwrap_or_else(|| String::from("humn"));
+
+		let Some(monkey) = monkeys.get(&name) else {
+			println!("Unknown monkey: {}", name);
+			return;
+		};
+		if !Graph::new(&name, &monkeys).is_valid() {
+			println!("{} depends on a missing monkey or a cycle", name);
+			return;
+		}
+
+		match &monkey.job {
+			MonkeyJob::MathOperation(name1, _, name2) if name == "root" => {
+				println!("{} = {}",
+					Expression::new(name1, &variable, &monkeys),
+					Expression::new(name2, &variable, &monkeys));
+			}
+			_ => println!("{} = {}", name, Expression::new(&name, &variable, &monkeys)),
+		}
+		return;
+	}
+	if mode.as_deref().is_some_and(|arg| arg.contains('=')) {
+		let mut evaluator = Evaluator::new(&monkeys);
+		match evaluator.get("root") {
+			Ok(value) => println!("Root: {}", value),
+			Err(err) => println!("Error: {}", err),
+		}
+
+		for arg in env::args().skip(1) {
+			let Some((name, Ok(value))) = arg.split_once('=')
+				.map(|(name, value)| (name, value.parse::<i64>())) else {
+				println!("Expected <monkey>=<number>, not {}", arg);
+				continue;
+			};
+
+			if !evaluator.set(name, value) {
+				println!("{} doesn't yell a number", name);
+				continue;
+			}
+			match evaluator.get("root") {
+				Ok(value) => println!("Root with {}: {}", arg, value),
+				Err(err) => println!("Error with {}: {}", arg, err),
+			}
+		}
+		return;
+	}
+	if mode.as_deref() == Some("dot") {
+		graph.write_dot(&monkeys, &mut stdout()).unwrap();
+		return;
+	}
+	if mode.as_deref() == Some("exact") {
+		let mut flagged = Vec::new();
+		match get_exact_value(&graph, &monkeys, &mut flagged) {
+			Ok(value) => println!("Root: {}", value),
+			Err(err) => println!("Error: {}", err),
+		}
+		for (name, value) in flagged {
+			if value.is_integer() {
+				println!("{}: {} overflows i64", name, value);
+			} else {
+				println!("{}: {} is not an integer", name, value);
+			}
+		}
+		return;
+	}
+
 	// Part 1
-	println!("Root: {}", get_value("root", &monkeys));
+	println!("Root: {}", get_values(&graph, &monkeys)["root"]);
 }
@@ -1,10 +1,15 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::env;
 use std::fs::File;
-use std::io::{BufRead, BufReader};
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
 
 type Tiles = HashMap<Position, TileType>;
 
-#[derive(Eq, PartialEq)]
+/// Every tile visited, with the facing when leaving it
+type Trail = Vec<(Position, Direction)>;
+
+#[derive(Eq, PartialEq, Copy, Clone)]
 enum TileType {
 	Open,
 	Wall,
@@ -22,6 +27,7 @@ enum Instruction {
 	Forward(usize),
 }
 
+#[derive(Eq, PartialEq, Copy, Clone)]
 enum Direction {
 	Right = 0,
 	Down = 1,
@@ -29,6 +35,38 @@ enum Direction {
 	Up = 3,
 }
 
+/// Dense copy of the tiles, with the first and last tile of every row and column
+struct Board {
+	width: i32,
+	height: i32,
+	tiles: Vec<Option<TileType>>,
+	rows: Vec<(i32, i32)>,
+	columns: Vec<(i32, i32)>,
+}
+
+type Vector = [i32; 3];
+
+/// Orientation of a face once folded into a cube
+struct Face {
+	normal: Vector,
+	right: Vector,
+	down: Vector,
+}
+
+/// Where you end up when walking off the edge of the board
+trait Wrapping {
+	fn wrap(&self, board: &Board, position: &Position, direction: Direction) -> (Position, Direction);
+}
+
+/// Continues from the other side of the same row or column
+struct FlatWrapping;
+
+/// Continues on the adjacent face after folding the board into a cube
+struct CubeWrapping {
+	size: i32,
+	faces: HashMap<Position, Face>,
+}
+
 impl TileType {
 	fn parse(ch: char) -> Option<Self> {
 		match ch {
@@ -54,6 +92,17 @@ impl Position {
 }
 
 impl Direction {
+	const ALL: [Direction; 4] = [Direction::Right, Direction::Down, Direction::Left, Direction::Up];
+
+	fn arrow(&self) -> char {
+		match self {
+			Direction::Right => '>',
+			Direction::Down => 'v',
+			Direction::Left => '<',
+			Direction::Up => '^',
+		}
+	}
+
 	fn left(&self) -> Self {
 		match self {
 			Direction::Right => Direction::Up,
@@ -72,6 +121,260 @@ impl Direction {
 	}
 }
 
+impl Board {
+	fn new(tiles: &Tiles) -> Self {
+		let width = tiles.keys().map(|pos| pos.x + 1).max().unwrap_or(0);
+		let height = tiles.keys().map(|pos| pos.y + 1).max().unwrap_or(0);
+
+		let mut board = Self {
+			width,
+			height,
+			tiles: vec![None; (width * height) as usize],
+			rows: vec![(i32::MAX, i32::MIN); height as usize],
+			columns: vec![(i32::MAX, i32::MIN); width as usize],
+		};
+
+		for (pos, tile) in tiles {
+			board.tiles[(pos.y * width + pos.x) as usize] = Some(*tile);
+
+			let row = &mut board.rows[pos.y as usize];
+			*row = (row.0.min(pos.x), row.1.max(pos.x));
+
+			let column = &mut board.columns[pos.x as usize];
+			*column = (column.0.min(pos.y), column.1.max(pos.y));
+		}
+
+		board
+	}
+
+	fn get(&self, pos: &Position) -> Option<TileType> {
+		if pos.x < 0 || pos.y < 0 || pos.x >= self.width || pos.y >= self.height {
+			None
+		} else {
+			self.tiles[(pos.y * self.width + pos.x) as usize]
+		}
+	}
+
+	/// Board in its original layout, with the last facing on each tile in the trail
+	fn render(&self, trail: &[(Position, Direction)]) -> String {
+		let mut arrows = HashMap::new();
+		for (pos, direction) in trail {
+			arrows.insert(*pos, direction.arrow());
+		}
+
+		(0..self.height)
+			.map(|y| (0..self.width)
+				.map(|x| {
+					let pos = Position::new(x, y);
+					match (arrows.get(&pos), self.get(&pos)) {
+						(Some(arrow), _) => *arrow,
+						(None, Some(TileType::Open)) => '.',
+						(None, Some(TileType::Wall)) => '#',
+						(None, None) => ' ',
+					}
+				})
+				.collect::<String>()
+				.trim_end()
+				.to_string())
+			.collect::<Vec<_>>()
+			.join("\n")
+	}
+
+	fn get_start_position(&self) -> Position {
+		(self.rows[0].0..=self.rows[0].1)
+			.map(|x| Position::new(x, 0))
+			.find(|pos| self.get(pos) == Some(TileType::Open))
+			.unwrap()
+	}
+}
+
+impl Wrapping for FlatWrapping {
+	fn wrap(&self, board: &Board, position: &Position, direction: Direction) -> (Position, Direction) {
+		let row = board.rows[position.y as usize];
+		let column = board.columns[position.x as usize];
+
+		let pos = match direction {
+			Direction::Right => Position::new(row.0, position.y),
+			Direction::Down => Position::new(position.x, column.0),
+			Direction::Left => Position::new(row.1, position.y),
+			Direction::Up => Position::new(position.x, column.1),
+		};
+		(pos, direction)
+	}
+}
+
+fn negate(vector: Vector) -> Vector {
+	vector.map(|value| -value)
+}
+
+fn dot(a: Vector, b: Vector) -> i32 {
+	a.iter().zip(b).map(|(a, b)| a * b).sum()
+}
+
+impl Face {
+	fn vector(&self, direction: Direction) -> Vector {
+		match direction {
+			Direction::Right => self.right,
+			Direction::Down => self.down,
+			Direction::Left => negate(self.right),
+			Direction::Up => negate(self.down),
+		}
+	}
+
+	/// Face on the other side of the edge in the direction, as if folded away from you
+	fn fold(&self, direction: Direction) -> Self {
+		let normal = self.vector(direction);
+		match direction {
+			Direction::Right => Self { normal, right: negate(self.normal), down: self.down },
+			Direction::Left => Self { normal, right: self.normal, down: self.down },
+			Direction::Down => Self { normal, right: self.right, down: negate(self.normal) },
+			Direction::Up => Self { normal, right: self.right, down: self.normal },
+		}
+	}
+}
+
+impl CubeWrapping {
+	/// Folds the board into a cube, or None if it isn't the net of one
+	fn new(board: &Board) -> Option<Self> {
+		let count = board.tiles.iter().filter(|tile| tile.is_some()).count();
+		let size = ((count / 6) as f64).sqrt() as i32;
+		if size == 0 || count != 6 * (size * size) as usize {
+			return None;
+		}
+
+		let start = board.get_start_position();
+		let start = Position::new(start.x / size, start.y / size);
+
+		let mut faces = HashMap::new();
+		faces.insert(start, Face {
+			normal: [0, 0, -1],
+			right: [1, 0, 0],
+			down: [0, 1, 0],
+		});
+
+		let mut queue = vec![start];
+		while let Some(current) = queue.pop() {
+			for direction in Direction::ALL {
+				let next = current.walk(&direction);
+				let corner = Position::new(next.x * size, next.y * size);
+				if board.get(&corner).is_none() || faces.contains_key(&next) {
+					continue;
+				}
+				let face = faces[&current].fold(direction);
+				faces.insert(next, face);
+				queue.push(next);
+			}
+		}
+
+		// Each face has to be completely on the board, and on its own side of the cube
+		let filled = faces.keys().all(|face| (0..size * size)
+			.map(|i| Position::new(face.x * size + i % size, face.y * size + i / size))
+			.all(|pos| board.get(&pos).is_some()));
+		let normals: HashSet<Vector> = faces.values().map(|face| face.normal).collect();
+		if faces.len() != 6 || normals.len() != 6 || !filled {
+			return None;
+		}
+
+		Some(Self { size, faces })
+	}
+
+	/// Face containing the tile, and where the tile is on it
+	fn face_of(&self, position: &Position) -> (&Face, Position) {
+		let face = Position::new(position.x / self.size, position.y / self.size);
+		let local = Position::new(position.x % self.size, position.y % self.size);
+		(&self.faces[&face], local)
+	}
+
+	/// Centre of the tile on a cube spanning -size to size on each axis
+	fn point_of(&self, face: &Face, local: &Position) -> Vector {
+		let x = 2 * local.x + 1 - self.size;
+		let y = 2 * local.y + 1 - self.size;
+		[0, 1, 2].map(|i| face.normal[i] * self.size + face.right[i] * x + face.down[i] * y)
+	}
+
+	fn tile_at(&self, point: Vector) -> (Position, &Face) {
+		let (origin, face) = self.faces.iter()
+			.find(|(_, face)| dot(face.normal, point) == self.size)
+			.unwrap();
+
+		let x = (dot(face.right, point) + self.size - 1) / 2;
+		let y = (dot(face.down, point) + self.size - 1) / 2;
+		(Position::new(origin.x * self.size + x, origin.y * self.size + y), face)
+	}
+
+	/// Every tile as a quad on the folded cube, walls and the trail using their own materials
+	fn write_obj(&self, board: &Board, trail: &[(Position, Direction)], material_file: &str,
+		out: &mut impl Write) -> std::io::Result<()> {
+		let visited: HashSet<Position> = trail.iter().map(|(pos, _)| *pos).collect();
+		let mut groups: [(&str, Vec<Position>); 3] = [
+			("open", Vec::new()),
+			("wall", Vec::new()),
+			("path", Vec::new()),
+		];
+
+		for y in 0..board.height {
+			for x in 0..board.width {
+				let pos = Position::new(x, y);
+				let group = match board.get(&pos) {
+					Some(_) if visited.contains(&pos) => 2,
+					Some(TileType::Open) => 0,
+					Some(TileType::Wall) => 1,
+					None => continue,
+				};
+				groups[group].1.push(pos);
+			}
+		}
+
+		writeln!(out, "mtllib {}", material_file)?;
+		let mut vertices = 0;
+
+		for (material, positions) in groups {
+			writeln!(out, "usemtl {}", material)?;
+			for pos in positions {
+				let (face, local) = self.face_of(&pos);
+				let centre = self.point_of(face, &local);
+
+				// Counter-clockwise seen from outside, as right x down points inwards
+				for (right, down) in [(-1, -1), (-1, 1), (1, 1), (1, -1)] {
+					let [x, y, z] = [0, 1, 2]
+						.map(|i| centre[i] + face.right[i] * right + face.down[i] * down);
+					writeln!(out, "v {} {} {}", x, y, z)?;
+				}
+				writeln!(out, "f {} {} {} {}", vertices + 1, vertices + 2, vertices + 3, vertices + 4)?;
+				vertices += 4;
+			}
+		}
+
+		Ok(())
+	}
+}
+
+fn write_materials(out: &mut impl Write) -> std::io::Result<()> {
+	for (material, colour) in [("open", "0.9 0.9 0.9"), ("wall", "0.2 0.2 0.2"), ("path", "0.9 0.2 0.2")] {
+		writeln!(out, "newmtl {}", material)?;
+		writeln!(out, "Kd {}", colour)?;
+	}
+	Ok(())
+}
+
+impl Wrapping for CubeWrapping {
+	fn wrap(&self, _: &Board, position: &Position, direction: Direction) -> (Position, Direction) {
+		let (face, local) = self.face_of(position);
+		let point = self.point_of(face, &local);
+
+		// Step over the edge, then continue down the side of the cube
+		let heading = face.vector(direction);
+		let point = [0, 1, 2].map(|i| point[i] + heading[i] - face.normal[i]);
+		let heading = negate(face.normal);
+
+		let (pos, next) = self.tile_at(point);
+		let direction = Direction::ALL.into_iter()
+			.find(|dir| next.vector(*dir) == heading)
+			.unwrap();
+		(pos, direction)
+	}
+}
+
 fn parse_tiles(line: &str) -> Vec<(usize, TileType)> {
 	line.chars().enumerate()
 		.map(|(i, char)| (i, TileType::parse(char)))
@@ -111,50 +414,23 @@ fn parse_instructions(line: &str) -> Vec<Instruction> {
 	instructions
 }
 
-fn get_start_position(tiles: &Tiles) -> Position {
-	tiles.iter()
-		.filter(|(pos, tile)| pos.y == 0 && tile == &&TileType::Open)
-		.map(|(&pos, _)| pos)
-		.min_by_key(|pos| pos.x)
-		.unwrap()
-}
-
-fn walk(tiles: &Tiles, position: &Position, direction: &Direction) -> Option<Position> {
-	let new_pos = position.walk(direction);
-	if let Some(tile) = tiles.get(&new_pos) {
-		match tile {
-			TileType::Open => Some(new_pos),
-			TileType::Wall => None,
-		}
-	} else {
-		let pos = match direction {
-			Direction::Right => tiles.keys()
-				.filter(|pos| pos.y == position.y)
-				.min_by_key(|pos| pos.x),
-
-			Direction::Down => tiles.keys()
-				.filter(|pos| pos.x == position.x)
-				.min_by_key(|pos| pos.y),
-
-			Direction::Left => tiles.keys()
-				.filter(|pos| pos.y == position.y)
-				.max_by_key(|pos| pos.x),
+fn walk(board: &Board, wrapping: &impl Wrapping, position: &Position,
+	direction: Direction) -> Option<(Position, Direction)> {
+	let mut next = (position.walk(&direction), direction);
+	if board.get(&next.0).is_none() {
+		next = wrapping.wrap(board, position, direction);
+	}
 
-			Direction::Up => tiles.keys()
-				.filter(|pos| pos.x == position.x)
-				.max_by_key(|pos| pos.y),
-		};
-		if pos.is_some() && tiles[pos.unwrap()] == TileType::Open {
-			pos.cloned()
-		} else {
-			None
-		}
+	match board.get(&next.0) {
+		Some(TileType::Open) => Some(next),
+		_ => None,
 	}
 }
 
-fn simulate(tiles: &Tiles, instructions: &[Instruction]) -> u32 {
+fn simulate(board: &Board, wrapping: &impl Wrapping, instructions: &[Instruction]) -> (u32, Trail) {
 	let mut direction = Direction::Right;
-	let mut position = get_start_position(tiles);
+	let mut position = board.get_start_position();
+	let mut trail = vec![(position, direction)];
 
 	for instruction in instructions {
 		match instruction {
@@ -162,19 +438,39 @@ fn simulate(tiles: &Tiles, instructions: &[Instruction]) -> u32 {
 			Instruction::Right => direction = direction.right(),
 			Instruction::Forward(steps) => {
 				for _ in 0..*steps {
-					if let Some(pos) = walk(tiles, &position, &direction) {
+					if let Some((pos, dir)) = walk(board, wrapping, &position, direction) {
 						position = pos;
+						direction = dir;
+						trail.push((position, direction));
 					} else {
 						break;
 					}
 				}
+				continue;
 			}
 		}
+		trail.push((position, direction));
 	}
 
-	1_000 * (position.y as u32 + 1)
+	let password = 1_000 * (position.y as u32 + 1)
 		+ 4 * (position.x as u32 + 1)
-		+ direction as u32
+		+ direction as u32;
+	(password, trail)
+}
+
+/// End of the trail covering the last moves, along with any turns after them
+fn last_moves(trail: &[(Position, Direction)], count: usize) -> &[(Position, Direction)] {
+	let mut start = trail.len();
+	let mut moves = 0;
+
+	// Turns are in the trail too, but leave the position as it was
+	while start > 0 && moves < count {
+		start -= 1;
+		if start == 0 || trail[start].0 != trail[start - 1].0 {
+			moves += 1;
+		}
+	}
+	&trail[start..]
 }
 
 fn main() {
@@ -194,6 +490,52 @@ fn main() {
 		}
 	}
 
+	let board = Board::new(&tiles);
+
+	let (password, flat_trail) = simulate(&board, &FlatWrapping, &instructions);
+	let cube = CubeWrapping::new(&board);
+	let cube_result = cube.as_ref().map(|cube| simulate(&board, cube, &instructions));
+
+	// Usage: day22 [path|cube-path] [last moves]
+	//    or: day22 obj <output.obj>
+	let args: Vec<String> = env::args().skip(1).collect();
+	let mode = args.first().map(|arg| arg.as_str());
+	if mode == Some("obj") && args.get(1).is_none() {
+		println!("Usage: day22 obj <output.obj>");
+		return;
+	}
+	if cube.is_none() && matches!(mode, Some("obj" | "cube-path")) {
+		println!("Board isn't the net of a cube");
+		println!();
+	}
+
+	if let (Some("obj"), Some(cube), Some((_, cube_trail))) = (mode, &cube, &cube_result) {
+		let path = Path::new(&args[1]);
+		let material_path = path.with_extension("mtl");
+		let material_file = material_path.file_name().unwrap().to_str().unwrap();
+
+		let mut out = BufWriter::new(File::create(path).unwrap());
+		cube.write_obj(&board, cube_trail, material_file, &mut out).unwrap();
+		write_materials(&mut BufWriter::new(File::create(&material_path).unwrap())).unwrap();
+		println!("Wrote cube to {}", path.display());
+	}
+
+	let trail = match mode {
+		Some("path") => Some(&flat_trail),
+		Some("cube-path") => cube_result.as_ref().map(|(_, trail)| trail),
+		_ => None,
+	};
+	if let Some(trail) = trail {
+		let last = args.get(1).map_or(trail.len(), |count| count.parse().unwrap());
+		println!("{}", board.render(last_moves(trail, last)));
+		println!();
+	}
+
 	// Part 1
-	println!("Password: {}", simulate(&tiles, &instructions[..]));
+	println!("Password: {}", password);
+
+	// Part 2
+	if let Some((cube_password, _)) = cube_result {
+		println!("Cube password: {}", cube_password);
+	}
 }
@@ -1,12 +1,63 @@
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
+use std::env;
 use std::fs::File;
-use std::io::{BufRead, BufReader, Error};
+use std::io::{BufRead, BufReader, BufWriter, Error, Write};
 
+#[derive(Copy, Clone)]
 enum Direction {
 	North,
+	NorthEast,
+	East,
+	SouthEast,
 	South,
+	SouthWest,
 	West,
-	East,
+	NorthWest,
+}
+
+/// Step an elf can propose, if none of the look-ahead tiles have elves
+struct Move {
+	direction: Direction,
+	look_ahead: u8,
+}
+
+enum Rotation {
+	/// Always consider moves in the same order
+	Fixed,
+	/// Move this many moves from the front to the back of the list after every round
+	Shift(usize),
+}
+
+enum Conflict {
+	/// Nobody moves if more than one elf proposes the same tile
+	Cancel,
+	/// The first elf in reading order gets to move
+	FirstWins,
+}
+
+/// How elves decide where to move each round
+struct Rules {
+	moves: Vec<Move>,
+	/// Elves stay where they are if none of these neighbours have elves
+	stay_unless: u8,
+	rotation: Rotation,
+	conflict: Conflict,
+}
+
+/// Summary of the map after a round
+struct RoundStats {
+	round: usize,
+	moved: usize,
+	top_left: Position,
+	bottom_right: Position,
+	empty_tiles: u32,
+}
+
+/// Runs rounds of the rules on a map
+struct Engine {
+	map: Map,
+	rules: Rules,
+	round: usize,
 }
 
 struct Map {
@@ -19,6 +70,13 @@ struct Position {
 	y: i32,
 }
 
+/// Offsets of the surrounding tiles, in the same order as the bits in a neighbour mask
+const NEIGHBOURS: [(i32, i32); 8] = [
+	(-1, -1), (0, -1), (1, -1),
+	(1, 0), (1, 1), (0, 1),
+	(-1, 1), (-1, 0),
+];
+
 impl Clone for Map {
 	fn clone(&self) -> Self {
 		Self {
@@ -73,117 +131,267 @@ impl Map {
 	}
 
 	fn empty_tile_count(&self) -> u32 {
-		let mut result = 0;
+		let min = self.top_left().unwrap();
+		let max = self.bottom_right().unwrap();
+
+		let area = (max.x - min.x + 1) as u32 * (max.y - min.y + 1) as u32;
+		area - self.elves.len() as u32
+	}
 
+	/// Smallest rectangle containing all elves, drawn like in the puzzle
+	fn render(&self) -> String {
 		let min = self.top_left().unwrap();
 		let max = self.bottom_right().unwrap();
 
-		for y in min.y..=max.y {
-			for x in min.x..=max.x {
-				if !self.elves.contains(&Position::new(x, y)) {
-					result += 1;
-				}
-			}
+		(min.y..=max.y)
+			.map(|y| (min.x..=max.x)
+				.map(|x| if self.elves.contains(&Position::new(x, y)) { '#' } else { '.' })
+				.collect::<String>())
+			.collect::<Vec<_>>()
+			.join("\n")
+	}
+}
+
+impl Direction {
+	/// Bit of the neighbour in the direction
+	fn bit(&self) -> u8 {
+		match self {
+			Direction::NorthWest => 1 << 0,
+			Direction::North => 1 << 1,
+			Direction::NorthEast => 1 << 2,
+			Direction::East => 1 << 3,
+			Direction::SouthEast => 1 << 4,
+			Direction::South => 1 << 5,
+			Direction::SouthWest => 1 << 6,
+			Direction::West => 1 << 7,
 		}
-		result
+	}
+
+	/// The neighbour in the direction, and the two next to it
+	fn mask(&self) -> u8 {
+		let bit = self.bit();
+		bit | bit.rotate_left(1) | bit.rotate_right(1)
 	}
 }
 
-impl Position {
-	fn new(x: i32, y: i32) -> Self {
-		Self { x, y }
+impl Move {
+	fn new(direction: Direction) -> Self {
+		Self {
+			direction,
+			look_ahead: direction.mask(),
+		}
 	}
+}
 
-	fn walk(&self, dir: &Direction) -> Self {
-		match dir {
-			Direction::North => Self::new(self.x, self.y - 1),
-			Direction::South => Self::new(self.x, self.y + 1),
-			Direction::West => Self::new(self.x - 1, self.y),
-			Direction::East => Self::new(self.x + 1, self.y),
+impl Rules {
+	/// Rules from the puzzle
+	fn cardinal() -> Self {
+		Self {
+			moves: [Direction::North, Direction::South, Direction::West, Direction::East]
+				.map(Move::new)
+				.into(),
+			stay_unless: u8::MAX,
+			rotation: Rotation::Shift(1),
+			conflict: Conflict::Cancel,
 		}
 	}
 
-	fn adjacent(&self, dir: &Direction) -> Vec<Self> {
-		match dir {
-			Direction::North | Direction::South => (-1..=1)
-				.map(|x| Position::new(self.x + x, self.y))
-				.collect(),
-			Direction::West | Direction::East => (-1..=1)
-				.map(|y| Position::new(self.x, self.y + y))
-				.collect()
+	/// Also allows moving diagonally, only checking the three tiles around the target
+	fn eight_directions() -> Self {
+		Self {
+			moves: [
+				Direction::North, Direction::South, Direction::West, Direction::East,
+				Direction::NorthEast, Direction::SouthWest, Direction::NorthWest, Direction::SouthEast,
+			].map(Move::new).into(),
+			..Self::cardinal()
 		}
 	}
 
-	fn proposed(&self, map: &Map, n: usize) -> Option<Self> {
-		let directions = [
-			Direction::North,
-			Direction::South,
-			Direction::West,
-			Direction::East,
-		];
+	fn order(&self, round: usize) -> impl Iterator<Item=&Move> {
+		let skip = match self.rotation {
+			Rotation::Fixed => 0,
+			Rotation::Shift(count) => round * count,
+		};
 
-		let options: Vec<&Direction> = directions.iter()
+		self.moves.iter()
 			.cycle()
-			.skip(n)
-			.take(directions.len())
-			.filter(|dir| self.walk(dir).adjacent(dir).iter()
-				.all(|pos| !map.elves.contains(pos)))
-			.collect();
+			.skip(skip % self.moves.len())
+			.take(self.moves.len())
+	}
+}
 
-		if options.len() == directions.len() {
-			None
-		} else {
-			options.first()
-				.map(|dir| self.walk(dir))
+impl Position {
+	fn new(x: i32, y: i32) -> Self {
+		Self { x, y }
+	}
+
+	fn walk(&self, dir: &Direction) -> Self {
+		let bit = dir.bit().trailing_zeros() as usize;
+		let (x, y) = NEIGHBOURS[bit];
+		Self::new(self.x + x, self.y + y)
+	}
+
+	/// Bit mask of which surrounding tiles have elves
+	fn neighbours(&self, map: &Map) -> u8 {
+		NEIGHBOURS.iter()
+			.enumerate()
+			.filter(|(_, (x, y))| map.elves.contains(&Position::new(self.x + x, self.y + y)))
+			.fold(0, |mask, (i, _)| mask | 1 << i)
+	}
+
+	fn proposed(&self, map: &Map, rules: &Rules, round: usize) -> Option<Self> {
+		let neighbours = self.neighbours(map);
+		if neighbours & rules.stay_unless == 0 {
+			return None;
 		}
+
+		rules.order(round)
+			.find(|option| neighbours & option.look_ahead == 0)
+			.map(|option| self.walk(&option.direction))
 	}
 }
 
-fn simulate(map: &mut Map, rounds: usize) -> usize {
-	for n in 0..rounds {
-		let mut elves: Vec<(Position, Option<Position>)> = map.elves.iter()
-			.map(|pos| (pos.clone(), pos.proposed(map, n)))
+impl Engine {
+	fn new(map: Map, rules: Rules) -> Self {
+		Self {
+			map,
+			rules,
+			round: 0,
+		}
+	}
+
+	/// Runs one round, returning how many elves moved
+	fn step(&mut self) -> usize {
+		let map = &self.map;
+		let mut proposals: Vec<(Position, Position)> = map.elves.iter()
+			.filter_map(|pos| pos.proposed(map, &self.rules, self.round)
+				.map(|new_pos| (*pos, new_pos)))
 			.collect();
 
-		for i in 0..elves.len() {
-			if let Some(new_pos) = elves[i].1 {
-				let same_pos: Vec<usize> = elves.iter().enumerate()
-					.filter(|(_, (_, pos))| pos.is_some() && pos.unwrap() == new_pos)
-					.map(|(i, _)| i)
-					.collect();
-				if same_pos.len() > 1 {
-					for i in same_pos {
-						elves[i].1 = None;
-					}
-				}
+		let mut counts: HashMap<Position, u8> = HashMap::new();
+		for (_, new_pos) in &proposals {
+			*counts.entry(*new_pos).or_default() += 1;
+		}
+
+		if let Conflict::FirstWins = self.rules.conflict {
+			proposals.sort_by_key(|(pos, _)| (pos.y, pos.x));
+		}
+
+		let mut moved = 0;
+		for (old_pos, new_pos) in proposals {
+			let count = counts.get_mut(&new_pos).unwrap();
+			let allowed = match self.rules.conflict {
+				Conflict::Cancel => *count == 1,
+				Conflict::FirstWins => *count > 0,
+			};
+
+			if allowed {
+				self.map.elves.remove(&old_pos);
+				assert!(self.map.elves.insert(new_pos));
+				*count = 0;
+				moved += 1;
 			}
 		}
 
-		if elves.iter().all(|(_, new_pos)| new_pos.is_none()) {
-			return n + 1;
+		self.round += 1;
+		moved
+	}
+
+	/// Runs until the rounds have passed or no elf moves,
+	/// returning the round where no elf moved, if any
+	fn run(&mut self, rounds: usize) -> Option<usize> {
+		for _ in 0..rounds {
+			if self.step() == 0 {
+				return Some(self.round);
+			}
 		}
+		None
+	}
+
+	/// Same as run, but calls the observer with the map and stats after every round
+	fn run_with(&mut self, rounds: usize, mut observer: impl FnMut(&Map, &RoundStats)) -> Option<usize> {
+		for _ in 0..rounds {
+			let moved = self.step();
 
-		for (old_pos, new_pos) in elves {
-			if let Some(new_pos) = new_pos {
-				map.elves.remove(&old_pos);
-				assert!(map.elves.insert(new_pos));
+			observer(&self.map, &RoundStats {
+				round: self.round,
+				moved,
+				top_left: self.map.top_left().unwrap(),
+				bottom_right: self.map.bottom_right().unwrap(),
+				empty_tiles: self.map.empty_tile_count(),
+			});
+
+			if moved == 0 {
+				return Some(self.round);
 			}
 		}
+		None
+	}
+}
+
+fn parse_rules(args: &[String]) -> Rules {
+	let mut rules = match args.first().map(|arg| arg.as_str()) {
+		Some("eight") => Rules::eight_directions(),
+		_ => Rules::cardinal(),
+	};
+
+	for arg in args {
+		if arg == "fixed" {
+			rules.rotation = Rotation::Fixed;
+		} else if let Some(count) = arg.strip_prefix("shift=") {
+			rules.rotation = Rotation::Shift(count.parse().unwrap());
+		} else if arg == "first-wins" {
+			rules.conflict = Conflict::FirstWins;
+		}
 	}
-	rounds
+
+	rules
 }
 
 fn main() {
 	let file = File::open("./day23/input").unwrap();
 	let lines = BufReader::new(file).lines();
 
-	let mut map1 = Map::parse(lines);
-	let mut map2 = map1.clone();
+	// Usage: day23 [cardinal|eight] [fixed|shift=<count>] [first-wins] [stats] [frames=<file>]
+	//    [rounds=<max rounds>]
+	let args: Vec<String> = env::args().skip(1).collect();
+	let max_rounds = args.iter()
+		.find_map(|arg| arg.strip_prefix("rounds="))
+		.map_or(100_000, |count| count.parse().unwrap());
+	let show_stats = args.iter().any(|arg| arg == "stats");
+	let mut frames = args.iter()
+		.find_map(|arg| arg.strip_prefix("frames="))
+		.map(|path| BufWriter::new(File::create(path).unwrap()));
+
+	let map = Map::parse(lines);
+	let mut engine1 = Engine::new(map.clone(), parse_rules(&args));
+	let mut engine2 = Engine::new(map, parse_rules(&args));
 
 	// Part 1
-	simulate(&mut map1, 10);
-	println!("Empty ground tiles: {}", map1.empty_tile_count());
+	engine1.run(10);
+	println!("Empty ground tiles: {}", engine1.map.empty_tile_count());
 
 	// Part 2
-	println!("Rounds: {}", simulate(&mut map2, usize::MAX));
+	let rounds = if !show_stats && frames.is_none() {
+		engine2.run(max_rounds)
+	} else {
+		engine2.run_with(max_rounds, |map, stats| {
+			if show_stats {
+				println!("Round {}: {} moved, {},{} to {},{}, {} empty",
+					stats.round, stats.moved,
+					stats.top_left.x, stats.top_left.y,
+					stats.bottom_right.x, stats.bottom_right.y,
+					stats.empty_tiles);
+			}
+			if let Some(out) = &mut frames {
+				writeln!(out, "== End of Round {} ==", stats.round).unwrap();
+				writeln!(out, "{}", map.render()).unwrap();
+				writeln!(out).unwrap();
+			}
+		})
+	};
+	match rounds {
+		Some(rounds) => println!("Rounds: {}", rounds),
+		None => println!("Rounds: did not converge within {} rounds", max_rounds),
+	}
 }
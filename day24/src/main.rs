@@ -1,17 +1,56 @@
-use std::cmp::max;
-use std::collections::{HashMap, HashSet};
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap, VecDeque};
+use std::env;
 use std::fs::File;
-use std::hash::Hash;
 use std::io::{BufRead, BufReader, Error};
 
 struct Map {
-	tiles: HashMap<Position, Vec<Tile>>,
-	width: u32,
-	height: u32,
+	width: i32,
+	height: i32,
+	start: Position,
+	end: Position,
+	blizzards: Blizzards,
 }
 
-struct MapCollection {
-	maps: Vec<Map>,
+/// Blizzards inside the walls for every minute until they repeat,
+/// as one bit mask per row, split into 64 bit words
+struct Blizzards {
+	height: usize,
+	period: usize,
+	words: usize,
+	rows: Vec<u64>,
+}
+
+/// Where the expedition is at every minute, starting at the minute it left
+struct Route {
+	start: usize,
+	positions: Vec<Position>,
+}
+
+/// Routes for every leg between waypoints, and how many states were expanded to find them
+struct Walk {
+	legs: Vec<Route>,
+	expanded: usize,
+}
+
+#[derive(Copy, Clone)]
+enum Search {
+	BreadthFirst,
+	/// Guided by the Manhattan distance left through the remaining waypoints
+	AStar,
+}
+
+/// State as (estimated arrival, minute, leg, position)
+type State = (Reverse<usize>, usize, usize, Position);
+
+/// Earliest minute each (leg, state index) was reached,
+/// and the position before it along with if a waypoint was reached since
+type Visited = HashMap<(usize, usize), (u32, Option<(Position, bool)>)>;
+
+/// States waiting to be expanded, in order of minute or of estimated arrival
+enum Frontier {
+	Queue(VecDeque<State>),
+	Heap(BinaryHeap<State>),
 }
 
 #[derive(Eq, PartialEq, Copy, Clone)]
@@ -24,135 +63,126 @@ enum Direction {
 
 #[derive(Eq, PartialEq, Copy, Clone)]
 enum Tile {
-	Start,
-	End,
+	Open,
 	Wall,
 	Blizzard(Direction),
 }
 
-#[derive(Eq, PartialEq, Hash, Copy, Clone, Debug)]
+#[derive(Eq, PartialEq, Ord, PartialOrd, Hash, Copy, Clone, Debug)]
 struct Position {
 	x: i32,
 	y: i32,
 }
 
+fn gcd(a: usize, b: usize) -> usize {
+	if b == 0 {
+		a
+	} else {
+		gcd(b, a % b)
+	}
+}
+
+fn lcm(a: usize, b: usize) -> usize {
+	a / gcd(a, b) * b
+}
+
 impl Map {
-	fn new() -> Self {
+	fn parse(lines: impl Iterator<Item=Result<String, Error>>) -> Self {
+		let rows: Vec<Vec<Tile>> = lines
+			.map(|line| line.unwrap())
+			.filter(|line| !line.trim().is_empty())
+			.map(|line| line.trim().chars().map(Tile::parse).collect())
+			.collect();
+
+		let width = rows[0].len() as i32;
+		let height = rows.len() as i32;
+
+		let opening = |y: usize| Position::new(rows[y].iter()
+			.position(|tile| *tile == Tile::Open)
+			.unwrap() as i32, y as i32);
+
 		Self {
-			tiles: HashMap::new(),
-			width: 0,
-			height: 0,
+			width,
+			height,
+			start: opening(0),
+			end: opening(rows.len() - 1),
+			blizzards: Blizzards::new(&rows),
 		}
 	}
 
-	fn parse(lines: impl Iterator<Item=Result<String, Error>>) -> Self {
-		let mut map = Map::new();
-
-		for (y, result) in lines.enumerate() {
-			map.height = max(map.height, y as u32 + 1);
-			for (x, char) in result.unwrap().chars().enumerate() {
-				map.width = max(map.width, x as u32 + 1);
-				if let Some(tile) = Tile::parse(char) {
-					map.tiles.insert(Position::new(x as i32, y as i32), vec![tile]);
-				}
-			}
-		}
+	/// If the position is the start, the end, or inside the walls
+	fn is_basin(&self, position: &Position) -> bool {
+		*position == self.start || *position == self.end
+			|| (position.x > 0 && position.x < self.width - 1
+			&& position.y > 0 && position.y < self.height - 1)
+	}
 
-		map.tiles.insert(Position::new(1, 0), vec![Tile::Start]);
-		map.tiles.insert(Position::new(map.width as i32 - 2, map.height as i32 - 1),
-			vec![Tile::End]);
+	/// If the expedition can be at the position during the minute
+	fn is_open(&self, position: &Position, minute: usize) -> bool {
+		*position == self.start || *position == self.end
+			|| (self.is_basin(position)
+			&& !self.blizzards.contains(position.x as usize - 1, position.y as usize - 1, minute))
+	}
 
-		map
+	/// Blizzards repeat, so being at the same position at the same point in the period is the same state
+	fn state_index(&self, position: &Position, minute: usize) -> usize {
+		let tile = (position.y * self.width + position.x) as usize;
+		(minute % self.blizzards.period) * (self.width * self.height) as usize + tile
 	}
+}
 
-	fn step(&self) -> Self {
-		let mut map: HashMap<Position, Vec<Tile>> = HashMap::new();
+impl Blizzards {
+	fn new(rows: &[Vec<Tile>]) -> Self {
+		let width = rows[0].len() - 2;
+		let height = rows.len() - 2;
+		let period = lcm(width, height);
+		let words = width.div_ceil(64);
 
-		for (position, tiles) in &self.tiles {
-			if let Some(Tile::Wall | Tile::Start | Tile::End) = tiles.first() {
-				assert_eq!(tiles.len(), 1);
-				map.insert(position.clone(), tiles.clone());
-			}
-		}
+		let mut blizzards = Self {
+			height,
+			period,
+			words,
+			rows: vec![0; period * height * words],
+		};
 
-		for (position, tiles) in &self.tiles {
-			for tile in tiles {
+		for (y, row) in rows[1..=height].iter().enumerate() {
+			for (x, tile) in row[1..=width].iter().enumerate() {
 				if let Tile::Blizzard(dir) = tile {
-					let new_pos = position.walk(Some(dir));
-					if let Some(existing) = map.get_mut(&new_pos) {
-						match existing.first() {
-							Some(Tile::Blizzard(_)) => existing.push(tile.clone()),
-							Some(Tile::Wall) => {
-								let pos = match dir {
-									Direction::Up => Position::new(
-										position.x,
-										self.height as i32 - 2,
-									),
-									Direction::Right => Position::new(1, position.y),
-									Direction::Down => Position::new(position.x, 1),
-									Direction::Left => Position::new(
-										self.width as i32 - 2,
-										position.y,
-									),
-								};
-								if let Some(existing) = map.get_mut(&pos) {
-									existing.push(tile.clone());
-								} else {
-									map.insert(pos, vec![tile.clone()]);
-								}
-							},
-							_ => panic!("Unknown tile"),
-						};
-					} else {
-						map.insert(new_pos, vec![tile.clone()]);
+					for minute in 0..period {
+						let pos = Position::new(x as i32, y as i32).walk_by(dir, minute as i32);
+						blizzards.insert(pos.x.rem_euclid(width as i32) as usize,
+							pos.y.rem_euclid(height as i32) as usize, minute);
 					}
 				}
 			}
 		}
 
-		Self {
-			tiles: map,
-			width: self.width,
-			height: self.height,
-		}
+		blizzards
 	}
 
-	fn find(&self, tile: &Tile) -> Option<Position> {
-		self.tiles.iter()
-			.find(|(_, tiles)| tiles.contains(tile))
-			.map(|(pos, _)| pos.clone())
+	fn index(&self, x: usize, y: usize, minute: usize) -> usize {
+		((minute % self.period) * self.height + y) * self.words + x / 64
 	}
-}
 
-impl MapCollection {
-	fn new(map: Map) -> Self {
-		Self {
-			maps: Vec::from([map]),
-		}
+	fn insert(&mut self, x: usize, y: usize, minute: usize) {
+		let index = self.index(x, y, minute);
+		self.rows[index] |= 1 << (x % 64);
 	}
 
-	fn get(&mut self, index: usize) -> &Map {
-		if index < self.maps.len() {
-			&self.maps[index]
-		} else {
-			for _ in self.maps.len()..=index {
-				let map = self.maps.last().unwrap().step();
-				self.maps.push(map);
-			}
-			self.maps.last().unwrap()
-		}
+	fn contains(&self, x: usize, y: usize, minute: usize) -> bool {
+		self.rows[self.index(x, y, minute)] & (1 << (x % 64)) != 0
 	}
 }
 
 impl Tile {
-	fn parse(ch: char) -> Option<Self> {
+	fn parse(ch: char) -> Self {
 		match ch {
-			'.' => None,
-			'#' => Some(Tile::Wall),
-			'<' => Some(Tile::Blizzard(Direction::Left)),
-			'>' => Some(Tile::Blizzard(Direction::Right)),
-			'^' => Some(Tile::Blizzard(Direction::Up)),
-			'v' => Some(Tile::Blizzard(Direction::Down)),
+			'.' => Tile::Open,
+			'#' => Tile::Wall,
+			'<' => Tile::Blizzard(Direction::Left),
+			'>' => Tile::Blizzard(Direction::Right),
+			'^' => Tile::Blizzard(Direction::Up),
+			'v' => Tile::Blizzard(Direction::Down),
 			_ => panic!("Unknown char: {}", ch),
 		}
 	}
@@ -165,66 +195,270 @@ impl Position {
 
 	fn walk(&self, dir: Option<&Direction>) -> Self {
 		match dir {
-			Some(Direction::Up) => Self::new(self.x, self.y - 1),
-			Some(Direction::Right) => Self::new(self.x + 1, self.y),
-			Some(Direction::Down) => Self::new(self.x, self.y + 1),
-			Some(Direction::Left) => Self::new(self.x - 1, self.y),
+			Some(dir) => self.walk_by(dir, 1),
 			None => Self::new(self.x, self.y),
 		}
 	}
+
+	fn walk_by(&self, dir: &Direction, steps: i32) -> Self {
+		match dir {
+			Direction::Up => Self::new(self.x, self.y - steps),
+			Direction::Right => Self::new(self.x + steps, self.y),
+			Direction::Down => Self::new(self.x, self.y + steps),
+			Direction::Left => Self::new(self.x - steps, self.y),
+		}
+	}
+
+	fn distance(&self, other: &Position) -> usize {
+		self.x.abs_diff(other.x) as usize + self.y.abs_diff(other.y) as usize
+	}
 }
 
-fn walk(maps: &mut MapCollection, start: &Position, end: &Position, initial: usize) -> usize {
-	let mut positions = HashSet::from([start.clone()]);
-	let mut steps = initial;
+impl Frontier {
+	fn new(search: Search) -> Self {
+		match search {
+			Search::BreadthFirst => Frontier::Queue(VecDeque::new()),
+			Search::AStar => Frontier::Heap(BinaryHeap::new()),
+		}
+	}
 
-	loop {
-		let mut options = HashSet::new();
-		let map = maps.get(steps);
-
-		for position in positions {
-			let directions = [
-				None,
-				Some(&Direction::Up),
-				Some(&Direction::Right),
-				Some(&Direction::Down),
-				Some(&Direction::Left),
-			];
-			for direction in directions {
-				let next = position.walk(direction);
-				if &next == end {
-					return steps;
-				}
-				if !map.tiles.contains_key(&next) && next.y > 0 && next.y < map.height as i32 {
-					options.insert(next);
-				}
+	fn push(&mut self, state: State) {
+		match self {
+			Frontier::Queue(queue) => queue.push_back(state),
+			Frontier::Heap(heap) => heap.push(state),
+		}
+	}
+
+	fn pop(&mut self) -> Option<State> {
+		match self {
+			Frontier::Queue(queue) => queue.pop_front(),
+			Frontier::Heap(heap) => heap.pop(),
+		}
+	}
+}
+
+impl Route {
+	/// Minute the expedition arrived
+	fn end(&self) -> usize {
+		self.start + self.positions.len() - 1
+	}
+
+	/// Minutes spent on the way
+	fn len(&self) -> usize {
+		self.positions.len() - 1
+	}
+
+	fn print(&self) {
+		for (minute, step) in self.positions.windows(2).enumerate() {
+			let (from, to) = (step[0], step[1]);
+			let action = match (to.x - from.x, to.y - from.y) {
+				(0, -1) => "move up to",
+				(1, 0) => "move right to",
+				(0, 1) => "move down to",
+				(-1, 0) => "move left to",
+				_ => "wait at",
+			};
+			println!("Minute {}: {} {},{}", self.start + minute + 1, action, to.x, to.y);
+		}
+	}
+}
+
+/// Fastest route leaving at the initial minute, if the end can be reached at all
+fn walk(map: &Map, start: &Position, end: &Position, initial: usize, search: Search) -> Option<Route> {
+	walk_waypoints(map, &[*start, *end], initial, search)
+		.map(|mut walk| walk.legs.remove(0))
+}
+
+/// Fastest route visiting the waypoints in order
+fn walk_waypoints(map: &Map, waypoints: &[Position], initial: usize, search: Search) -> Option<Walk> {
+	let mut walk = Walk {
+		legs: Vec::new(),
+		expanded: 0,
+	};
+	let mut minute = initial;
+	let mut first = 0;
+
+	// Waiting is always possible at the start and the end, so arriving there as early as possible
+	// is never worse, and the legs on either side can be searched on their own
+	for (i, waypoint) in waypoints.iter().enumerate().skip(1) {
+		if i == waypoints.len() - 1 || *waypoint == map.start || *waypoint == map.end {
+			let legs = walk_legs(map, &waypoints[first..=i], minute, search, &mut walk.expanded)?;
+			minute = legs.last().unwrap().end();
+			walk.legs.extend(legs);
+			first = i;
+		}
+	}
+
+	Some(walk)
+}
+
+/// Fastest route through all the waypoints at once, as one route per leg,
+/// counting the states expanded to find it
+fn walk_legs(map: &Map, waypoints: &[Position], initial: usize, search: Search,
+	expanded: &mut usize) -> Option<Vec<Route>> {
+	let last = waypoints.len() - 1;
+
+	// Distance left from each waypoint to the last one
+	let mut remaining = vec![0; waypoints.len()];
+	for leg in (0..last).rev() {
+		remaining[leg] = remaining[leg + 1] + waypoints[leg].distance(&waypoints[leg + 1]);
+	}
+	let estimate = |position: &Position, leg: usize| match search {
+		Search::BreadthFirst => 0,
+		Search::AStar if leg == last => 0,
+		Search::AStar => position.distance(&waypoints[leg + 1]) + remaining[leg + 1],
+	};
+
+	let mut visited = Visited::new();
+	visited.insert((0, map.state_index(&waypoints[0], initial)), (initial as u32, None));
+
+	// Lowest estimated arrival first, and the one furthest along when tied
+	let mut queue = Frontier::new(search);
+	queue.push((Reverse(initial + estimate(&waypoints[0], 0)), initial, 0, waypoints[0]));
+
+	while let Some((_, minute, leg, position)) = queue.pop() {
+		if visited[&(leg, map.state_index(&position, minute))].0 < minute as u32 {
+			continue;
+		}
+		*expanded += 1;
+
+		if leg == last {
+			let path = get_path(map, &visited, (position, minute, leg));
+			return Some(get_legs(&path));
+		}
+
+		let directions = [
+			None,
+			Some(&Direction::Up),
+			Some(&Direction::Right),
+			Some(&Direction::Down),
+			Some(&Direction::Left),
+		];
+		for direction in directions {
+			let next = position.walk(direction);
+			if !map.is_open(&next, minute + 1) {
+				continue;
+			}
+
+			let next_leg = if next == waypoints[leg + 1] { leg + 1 } else { leg };
+			let key = (next_leg, map.state_index(&next, minute + 1));
+			if visited.get(&key).is_none_or(|(other, _)| minute as u32 + 1 < *other) {
+				visited.insert(key, (minute as u32 + 1, Some((position, leg != next_leg))));
+				queue.push((Reverse(minute + 1 + estimate(&next, next_leg)), minute + 1, next_leg, next));
 			}
 		}
+	}
+
+	None
+}
+
+/// States leading up to and including the state, as (position, minute, leg)
+fn get_path(map: &Map, visited: &Visited, state: (Position, usize, usize)) -> Vec<(Position, usize, usize)> {
+	let mut path = vec![state];
+	let mut current = state;
 
-		positions = if options.len() > 0 {
-			options
-		} else {
-			HashSet::from([start.clone()])
+	loop {
+		let (position, minute, leg) = current;
+		let (_, Some((parent, new_leg))) = visited[&(leg, map.state_index(&position, minute))] else {
+			break;
 		};
+		current = (parent, minute - 1, if new_leg { leg - 1 } else { leg });
+		path.push(current);
+	}
+
+	path.reverse();
+	path
+}
+
+/// Splits the path into one route per leg
+fn get_legs(path: &[(Position, usize, usize)]) -> Vec<Route> {
+	let (start, initial, _) = path[0];
+	let mut legs = Vec::new();
+	let mut route = Route {
+		start: initial,
+		positions: vec![start],
+	};
+
+	for window in path.windows(2) {
+		let (position, minute, leg) = window[1];
+		route.positions.push(position);
 
-		steps += 1;
+		if leg != window[0].2 {
+			legs.push(route);
+			route = Route {
+				start: minute,
+				positions: vec![position],
+			};
+		}
+	}
+
+	legs
+}
+
+fn parse_waypoint(map: &Map, value: &str) -> Result<Position, String> {
+	let position = match value {
+		"start" => map.start,
+		"end" => map.end,
+		_ => value.trim_matches(|ch| ch == '(' || ch == ')')
+			.split_once(',')
+			.and_then(|(x, y)| Some(Position::new(x.trim().parse().ok()?, y.trim().parse().ok()?)))
+			.ok_or_else(|| format!("Waypoint should be start, end or x,y, not {}", value))?,
+	};
+
+	if !map.is_basin(&position) {
+		return Err(format!("Waypoint {},{} is not inside the basin", position.x, position.y));
 	}
+	Ok(position)
 }
 
 fn main() {
 	let file = File::open("./day24/input").unwrap();
 	let lines = BufReader::new(file).lines();
 
-	let mut maps = MapCollection::new(Map::parse(lines));
-	let start = maps.get(0).find(&Tile::Start).unwrap();
-	let end = maps.get(0).find(&Tile::End).unwrap();
+	let map = Map::parse(lines);
+	let start = map.start;
+	let end = map.end;
+
+	// Usage: day24 [route] [astar] [waypoint...], where a waypoint is start, end or x,y
+	let args: Vec<String> = env::args().skip(1).collect();
+	let show_route = args.iter().any(|arg| arg == "route");
+	let search = if args.iter().any(|arg| arg == "astar") {
+		Search::AStar
+	} else {
+		Search::BreadthFirst
+	};
+	let waypoints: Result<Vec<Position>, String> = args.iter()
+		.filter(|arg| *arg != "route" && *arg != "astar")
+		.map(|arg| parse_waypoint(&map, arg))
+		.collect();
+	let mut waypoints = match waypoints {
+		Ok(waypoints) => waypoints,
+		Err(err) => {
+			println!("{}", err);
+			return;
+		}
+	};
+	if waypoints.len() < 2 {
+		waypoints = vec![start, end, start, end];
+	}
 
 	// Part 1
-	let trip1 = walk(&mut maps, &start, &end, 0);
-	println!("First trip: {}", trip1);
+	let trip1 = walk(&map, &start, &end, 0, search).expect("No way to the end");
+	if show_route {
+		trip1.print();
+	}
+	println!("First trip: {} ({} minutes)", trip1.end(), trip1.len());
 
 	// Part 2
-	let trip2 = walk(&mut maps, &end, &start, trip1);
-	let trip3 = walk(&mut maps, &start, &end, trip2);
-	println!("Third trip: {}", trip3);
+	let walk = walk_waypoints(&map, &waypoints, 0, search).expect("No way past all waypoints");
+	let legs = walk.legs;
+	for (i, leg) in legs.iter().enumerate() {
+		if show_route {
+			leg.print();
+		}
+		let (from, to) = (waypoints[i], waypoints[i + 1]);
+		println!("Leg {}: {},{} to {},{} in {} minutes",
+			i + 1, from.x, from.y, to.x, to.y, leg.len());
+	}
+	println!("Last trip: {} ({} states expanded)", legs.last().unwrap().end(), walk.expanded);
 }
@@ -1,53 +1,415 @@
+use std::cmp::Ordering;
+use std::env;
+use std::fmt::{Display, Formatter};
 use std::fs::File;
-use std::io::{BufRead, BufReader};
+use std::io::{stdin, BufRead, BufReader};
+use std::iter::Sum;
+use std::ops::{Add, Mul, Neg, Sub};
+use std::str::FromStr;
 
-fn parse_snafu(value: char) -> i64 {
-	match value {
-		'2' => 2,
-		'1' => 1,
-		'0' => 0,
-		'-' => -1,
-		'=' => -2,
-		_ => panic!("Unknown char"),
+/// Balanced base 5 number of any size, least significant digit first,
+/// without any leading zeros
+#[derive(Clone, Eq, PartialEq, Debug)]
+struct Snafu {
+	digits: Vec<i8>,
+}
+
+/// Digits of a balanced base, from the lowest value to the highest
+struct Alphabet {
+	digits: Vec<char>,
+}
+
+#[derive(Debug)]
+enum ConvertError {
+	UnknownDigit(char),
+	/// Alphabet has an even number of digits, or less than three
+	InvalidBase(usize),
+	DuplicateDigit(char),
+	/// Value doesn't fit in an i64
+	Overflow,
+}
+
+/// Carries values outside of the balanced digit range over to the next digit,
+/// least significant digit first
+fn carry(values: &[i64], base: i64) -> Vec<i64> {
+	let mut digits = Vec::with_capacity(values.len() + 1);
+	let mut carry = 0_i128;
+	let mut i = 0;
+
+	// Wider than the values, so carrying from i64::MIN can't overflow
+	while i < values.len() || carry != 0 {
+		let value = *values.get(i).unwrap_or(&0) as i128 + carry;
+		let mut digit = value.rem_euclid(base as i128);
+		if digit > base as i128 / 2 {
+			digit -= base as i128;
+		}
+		carry = (value - digit) / base as i128;
+		digits.push(digit as i64);
+		i += 1;
+	}
+
+	while digits.last() == Some(&0) {
+		digits.pop();
+	}
+	digits
+}
+
+impl Alphabet {
+	fn new(digits: &str) -> Result<Self, ConvertError> {
+		let digits: Vec<char> = digits.chars().collect();
+		if digits.len() < 3 || digits.len().is_multiple_of(2) {
+			return Err(ConvertError::InvalidBase(digits.len()));
+		}
+		for (i, digit) in digits.iter().enumerate() {
+			if digits[..i].contains(digit) {
+				return Err(ConvertError::DuplicateDigit(*digit));
+			}
+		}
+		Ok(Self { digits })
+	}
+
+	fn snafu() -> Self {
+		Self::new("=-012").unwrap()
+	}
+
+	fn base(&self) -> i64 {
+		self.digits.len() as i64
+	}
+
+	fn value(&self, digit: char) -> Result<i64, ConvertError> {
+		self.digits.iter()
+			.position(|other| *other == digit)
+			.map(|index| index as i64 - self.base() / 2)
+			.ok_or(ConvertError::UnknownDigit(digit))
+	}
+
+	fn digit(&self, value: i64) -> char {
+		self.digits[(value + self.base() / 2) as usize]
+	}
+
+	/// Digits of the number, least significant first, without leading zeros
+	fn parse(&self, value: &str) -> Result<Vec<i64>, ConvertError> {
+		let digits = value.trim().chars()
+			.rev()
+			.map(|digit| self.value(digit))
+			.collect::<Result<Vec<i64>, ConvertError>>()?;
+		Ok(carry(&digits, self.base()))
+	}
+
+	fn format(&self, digits: &[i64]) -> String {
+		if digits.is_empty() {
+			return self.digit(0).to_string();
+		}
+		digits.iter().rev().map(|digit| self.digit(*digit)).collect()
+	}
+
+	/// Same number written using another alphabet
+	fn convert(&self, value: &str, to: &Alphabet) -> Result<String, ConvertError> {
+		let digits = self.parse(value)?;
+		let mut result = Vec::new();
+
+		for digit in digits.iter().rev() {
+			let mut values: Vec<i64> = result.iter().map(|value| value * self.base()).collect();
+			if values.is_empty() {
+				values.push(0);
+			}
+			values[0] += digit;
+			result = carry(&values, to.base());
+		}
+
+		Ok(to.format(&result))
+	}
+}
+
+#[derive(Debug)]
+enum ExpressionError {
+	Convert(ConvertError),
+	InvalidDecimal(String),
+	/// Operand where an operator was expected, or the other way around
+	Unexpected(String),
+	MissingOperand,
+}
+
+impl Display for ConvertError {
+	fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+		match self {
+			ConvertError::UnknownDigit(digit) => write!(f, "Unknown digit: {}", digit),
+			ConvertError::InvalidBase(base) => write!(f, "Base must be odd and at least 3, not {}", base),
+			ConvertError::DuplicateDigit(digit) => write!(f, "Duplicate digit: {}", digit),
+			ConvertError::Overflow => write!(f, "Value doesn't fit in an i64"),
+		}
+	}
+}
+
+impl Display for ExpressionError {
+	fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+		match self {
+			ExpressionError::Convert(err) => write!(f, "{}", err),
+			ExpressionError::InvalidDecimal(value) => write!(f, "Invalid decimal: {}", value),
+			ExpressionError::Unexpected(token) => write!(f, "Unexpected: {}", token),
+			ExpressionError::MissingOperand => write!(f, "Missing operand"),
+		}
+	}
+}
+
+impl From<ConvertError> for ExpressionError {
+	fn from(err: ConvertError) -> Self {
+		ExpressionError::Convert(err)
+	}
+}
+
+impl Snafu {
+	fn zero() -> Self {
+		Self { digits: Vec::new() }
+	}
+
+	/// Carries values outside of -2..=2 over to the next digit
+	fn normalize(values: Vec<i64>) -> Self {
+		Self {
+			digits: carry(&values, 5).into_iter().map(|digit| digit as i8).collect(),
+		}
+	}
+
+	fn signum(&self) -> i8 {
+		// The most significant digit outweighs all the others
+		self.digits.last().map_or(0, |digit| digit.signum())
+	}
+}
+
+impl From<i64> for Snafu {
+	fn from(value: i64) -> Self {
+		Snafu::normalize(vec![value])
+	}
+}
+
+impl TryFrom<&Snafu> for i64 {
+	type Error = ConvertError;
+
+	fn try_from(value: &Snafu) -> Result<Self, Self::Error> {
+		// Wider than the result, as the last digit can bring it back into range
+		value.digits.iter()
+			.rev()
+			.try_fold(0_i128, |num, digit| num.checked_mul(5)
+				.and_then(|num| num.checked_add(*digit as i128)))
+			.and_then(|num| i64::try_from(num).ok())
+			.ok_or(ConvertError::Overflow)
+	}
+}
+
+impl FromStr for Snafu {
+	type Err = ConvertError;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		Ok(Snafu::normalize(Alphabet::snafu().parse(s)?))
+	}
+}
+
+impl Display for Snafu {
+	fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+		let digits: Vec<i64> = self.digits.iter().map(|digit| *digit as i64).collect();
+		write!(f, "{}", Alphabet::snafu().format(&digits))
+	}
+}
+
+impl Add for &Snafu {
+	type Output = Snafu;
+
+	fn add(self, rhs: Self) -> Self::Output {
+		let len = self.digits.len().max(rhs.digits.len());
+		Snafu::normalize((0..len)
+			.map(|i| *self.digits.get(i).unwrap_or(&0) as i64
+				+ *rhs.digits.get(i).unwrap_or(&0) as i64)
+			.collect())
+	}
+}
+
+impl Neg for &Snafu {
+	type Output = Snafu;
+
+	fn neg(self) -> Self::Output {
+		Snafu {
+			digits: self.digits.iter().map(|digit| -digit).collect(),
+		}
+	}
+}
+
+impl Sub for &Snafu {
+	type Output = Snafu;
+
+	fn sub(self, rhs: Self) -> Self::Output {
+		self + &-rhs
+	}
+}
+
+impl Mul for &Snafu {
+	type Output = Snafu;
+
+	fn mul(self, rhs: Self) -> Self::Output {
+		if self.digits.is_empty() || rhs.digits.is_empty() {
+			return Snafu::zero();
+		}
+
+		let mut values = vec![0_i64; self.digits.len() + rhs.digits.len()];
+		for (i, a) in self.digits.iter().enumerate() {
+			for (j, b) in rhs.digits.iter().enumerate() {
+				values[i + j] += *a as i64 * *b as i64;
+			}
+		}
+		Snafu::normalize(values)
+	}
+}
+
+impl Add for Snafu {
+	type Output = Snafu;
+
+	fn add(self, rhs: Self) -> Self::Output {
+		&self + &rhs
+	}
+}
+
+impl Sub for Snafu {
+	type Output = Snafu;
+
+	fn sub(self, rhs: Self) -> Self::Output {
+		&self - &rhs
+	}
+}
+
+impl Mul for Snafu {
+	type Output = Snafu;
+
+	fn mul(self, rhs: Self) -> Self::Output {
+		&self * &rhs
+	}
+}
+
+impl Neg for Snafu {
+	type Output = Snafu;
+
+	fn neg(self) -> Self::Output {
+		-&self
 	}
 }
 
-fn from_snafu(value: &str) -> i64 {
-	value.chars()
-		.map(|char| parse_snafu(char))
-		.rev()
-		.enumerate()
-		.fold(0, |num, (i, digit)| num + (digit * 5_i64.pow(i as u32)))
+impl Sum for Snafu {
+	fn sum<I: Iterator<Item=Self>>(iter: I) -> Self {
+		iter.fold(Snafu::zero(), |sum, value| &sum + &value)
+	}
 }
 
-fn to_snafu(value: i64) -> String {
-	let mut remaining = value;
-	let mut result = String::new();
-	while remaining > 0 {
-		let str = match remaining % 5 {
-			0 => '0',
-			1 => '1',
-			2 => '2',
-			3 => '=',
-			4 => '-',
-			_ => panic!("Unexpected result")
-		};
-		remaining = (remaining - parse_snafu(str)) / 5;
-		result.insert(0, str);
+impl Ord for Snafu {
+	fn cmp(&self, other: &Self) -> Ordering {
+		(self - other).signum().cmp(&0)
+	}
+}
+
+impl PartialOrd for Snafu {
+	fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+		Some(self.cmp(other))
+	}
+}
+
+const REPL_HELP: &str = "Numbers are SNAFU, or decimal with a # prefix, like #-12. \
+Operators +, - and * need spaces around them, as - on its own is the SNAFU number -1.";
+
+/// SNAFU number, or decimal number if prefixed with #
+fn parse_operand(token: &str) -> Result<Snafu, ExpressionError> {
+	match token.strip_prefix('#') {
+		Some(decimal) => decimal.parse::<i64>()
+			.map(Snafu::from)
+			.map_err(|_| ExpressionError::InvalidDecimal(decimal.to_string())),
+		// Where a number is expected, - is -1 rather than an operator
+		None if matches!(token, "+" | "*") => Err(ExpressionError::Unexpected(token.to_string())),
+		None => Ok(token.parse()?),
+	}
+}
+
+/// Evaluates operands and operators separated by whitespace, with * before + and -
+fn evaluate(expression: &str) -> Result<Snafu, ExpressionError> {
+	let mut tokens = expression.split_whitespace();
+	let mut sum = Snafu::zero();
+	let mut product = parse_operand(tokens.next().ok_or(ExpressionError::MissingOperand)?)?;
+	let mut negative = false;
+
+	while let Some(operator) = tokens.next() {
+		if !matches!(operator, "+" | "-" | "*") {
+			return Err(ExpressionError::Unexpected(operator.to_string()));
+		}
+		let operand = parse_operand(tokens.next().ok_or(ExpressionError::MissingOperand)?)?;
+		match operator {
+			"*" => product = &product * &operand,
+			_ => {
+				sum = if negative { &sum - &product } else { &sum + &product };
+				negative = operator == "-";
+				product = operand;
+			}
+		}
+	}
+
+	Ok(if negative { &sum - &product } else { &sum + &product })
+}
+
+/// Evaluates each line from stdin, printing the result in SNAFU and decimal, or the syntax for help
+fn repl() {
+	for line in stdin().lock().lines().map_while(|line| line.ok()) {
+		if line.trim().is_empty() {
+			continue;
+		}
+		if line.trim() == "help" {
+			println!("{}", REPL_HELP);
+			continue;
+		}
+
+		match evaluate(&line) {
+			Ok(value) => match i64::try_from(&value) {
+				Ok(decimal) => println!("{} = {}", value, decimal),
+				Err(_) => println!("{}", value),
+			},
+			Err(err) => println!("Error: {} (type help for the syntax)", err),
+		}
 	}
-	result
 }
 
 fn main() {
+	// Usage: day25 convert <from digits> <to digits> <value>, with digits from lowest to highest
+	// Usage: day25 repl, with expressions like "2=-01 + #12 * 1=" from stdin
+	let args: Vec<String> = env::args().skip(1).collect();
+	if args.first().is_some_and(|arg| arg == "repl") {
+		repl();
+		return;
+	}
+
+	if args.first().is_some_and(|arg| arg == "convert") && args.len() == 4 {
+		let result = Alphabet::new(&args[1])
+			.and_then(|from| Alphabet::new(&args[2])
+				.and_then(|to| from.convert(&args[3], &to)));
+		match result {
+			Ok(value) => println!("{}", value),
+			Err(err) => println!("Error: {}", err),
+		}
+		return;
+	}
+
 	let file = File::open("./day25/input").unwrap();
 	let lines: Vec<String> = BufReader::new(file).lines()
-		.filter_map(|line| line.ok())
+		.map_while(|line| line.ok())
+		.filter(|line| !line.trim().is_empty())
 		.collect();
 
-	let sum = lines.iter()
-		.map(|line| from_snafu(line))
-		.sum::<i64>();
+	let mut sum = Snafu::zero();
+	for (i, line) in lines.iter().enumerate() {
+		match line.parse::<Snafu>() {
+			Ok(value) => sum = &sum + &value,
+			Err(err) => {
+				println!("Line {}: {}", i + 1, err);
+				return;
+			}
+		}
+	}
 
 	// Part 1
-	println!("Sum: {}", to_snafu(sum));
+	println!("Sum: {}", sum);
+	if let Ok(decimal) = i64::try_from(&sum) {
+		println!("Decimal sum: {}", decimal);
+	}
 }